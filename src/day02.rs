@@ -1,44 +1,181 @@
 use aoc_2024::util;
+use std::fmt;
 
-fn is_safe(report: &[i64]) -> bool {
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Violation {
+    NonMonotonic,
+    ZeroStep,
+    // Holds the maximum allowed step.
+    StepTooLarge(u64),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct Rules {
+    max_step: u64,
+    // How many levels may be removed to make a report safe.
+    tolerance: usize,
+}
+
+// The first place a report breaks the rules: `index` is the later level of the offending pair.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct Unsafety {
+    index: usize,
+    violation: Violation,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Verdict {
+    Safe,
+    // Safe once the levels at the given indices are removed.
+    SafeWithRemovals(Vec<usize>),
+    Unsafe(Unsafety),
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Violation::NonMonotonic => write!(f, "not monotonic"),
+            Violation::ZeroStep => write!(f, "step of zero"),
+            Violation::StepTooLarge(max_step) => write!(f, "step > {}", max_step),
+        }
+    }
+}
+
+impl fmt::Display for Verdict {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Verdict::Safe => write!(f, "safe"),
+            Verdict::SafeWithRemovals(indices) => {
+                write!(f, "safe after removing indices {:?}", indices)
+            }
+            Verdict::Unsafe(u) => write!(f, "unsafe at index {}: {}", u.index, u.violation),
+        }
+    }
+}
+
+fn check(report: &[i64], max_step: u64) -> Result<(), Unsafety> {
     if report.len() <= 1 {
-        return true;
+        return Ok(());
     }
     let increasing = report[0] < report[1];
     let mut last = report[0];
-    for &n in report.iter().skip(1) {
-        if last == n {
-            return false;
-        }
-        if increasing != (last < n) {
-            return false;
-        }
-        if last.abs_diff(n) > 3 {
-            return false;
-        }
-        last = n;
+    for (index, &n) in report.iter().enumerate().skip(1) {
+        let violation = if last == n {
+            Violation::ZeroStep
+        } else if increasing != (last < n) {
+            Violation::NonMonotonic
+        } else if last.abs_diff(n) > max_step {
+            Violation::StepTooLarge(max_step)
+        } else {
+            last = n;
+            continue;
+        };
+        return Err(Unsafety { index, violation });
     }
-    true
+    Ok(())
 }
 
-fn is_safe_dampened(report: &[i64]) -> bool {
-    if is_safe(report) {
-        return true;
+// Find the fewest removals (at most `rules.tolerance`) that leave `report` strictly increasing
+// (or decreasing) with steps of at most `rules.max_step`, returning the removed indices.
+//
+// dp[j] is the fewest removals among report[..=j] for a safe prefix ending at (and keeping) j,
+// along with the previously kept index. Since at most `tolerance` levels can be skipped, only the
+// last `tolerance + 1` kept candidates need considering, so this is O(n * (tolerance + 1)).
+fn removals_for_direction(report: &[i64], increasing: bool, rules: &Rules) -> Option<Vec<usize>> {
+    let len = report.len();
+    if len == 0 {
+        return Some(Vec::new());
     }
-    for i in 0..report.len() {
-        let start = report.iter().take(i);
-        let end = report.iter().skip(i + 1);
-        if is_safe(&start.chain(end).cloned().collect::<Vec<_>>()) {
-            return true;
+    let tolerance = rules.tolerance;
+    let valid_step = |from: i64, to: i64| {
+        from != to && (from < to) == increasing && from.abs_diff(to) <= rules.max_step
+    };
+
+    let mut dp: Vec<Option<(usize, Option<usize>)>> = vec![None; len];
+    for j in 0..len {
+        // Removing everything before j.
+        let mut best = if j <= tolerance {
+            Some((j, None))
+        } else {
+            None
+        };
+        for i in j.saturating_sub(tolerance + 1)..j {
+            let Some((removed, _)) = dp[i] else {
+                continue;
+            };
+            let removed = removed + (j - i - 1);
+            if removed <= tolerance
+                && valid_step(report[i], report[j])
+                && best.is_none_or(|(b, _)| removed < b)
+            {
+                best = Some((removed, Some(i)));
+            }
         }
+        dp[j] = best;
     }
-    false
+
+    // Removing everything after the last kept level.
+    let (_, mut last) = (len.saturating_sub(tolerance + 1)..len)
+        .filter_map(|j| dp[j].map(|(removed, _)| (removed + (len - 1 - j), j)))
+        .filter(|&(removed, _)| removed <= tolerance)
+        .min()?;
+
+    let mut kept = vec![false; len];
+    loop {
+        kept[last] = true;
+        match dp[last].unwrap().1 {
+            Some(prev) => last = prev,
+            None => break,
+        }
+    }
+    Some((0..len).filter(|&i| !kept[i]).collect())
+}
+
+fn check_dampened(report: &[i64], rules: &Rules) -> Verdict {
+    let unsafety = match check(report, rules.max_step) {
+        Ok(()) => return Verdict::Safe,
+        Err(unsafety) => unsafety,
+    };
+    [true, false]
+        .iter()
+        .filter_map(|&increasing| removals_for_direction(report, increasing, rules))
+        .min_by_key(|removed| removed.len())
+        .map_or(Verdict::Unsafe(unsafety), Verdict::SafeWithRemovals)
 }
 
-fn count_safe(reports: &[Vec<i64>], checker: fn(&[i64]) -> bool) -> usize {
+fn is_safe(report: &[i64], rules: &Rules) -> bool {
+    check(report, rules.max_step).is_ok()
+}
+
+fn is_safe_dampened(report: &[i64], rules: &Rules) -> bool {
+    !matches!(check_dampened(report, rules), Verdict::Unsafe(_))
+}
+
+fn count_safe(reports: &[Vec<i64>], checker: impl Fn(&[i64]) -> bool) -> usize {
     reports.iter().filter(|r| checker(r)).count()
 }
 
+// Print each report along with why (and where) it is or isn't safe.
+fn explain(reports: &[Vec<i64>], rules: &Rules) {
+    for report in reports {
+        let line = report
+            .iter()
+            .map(|n| n.to_string())
+            .collect::<Vec<_>>()
+            .join(" ");
+        let strict = match check(report, rules.max_step) {
+            Ok(()) => Verdict::Safe,
+            Err(unsafety) => Verdict::Unsafe(unsafety),
+        };
+        println!(
+            "{}: {}; dampened: {}",
+            line,
+            strict,
+            check_dampened(report, rules)
+        );
+    }
+}
+
 fn main() {
     let mut reports = Vec::new();
     for line in util::get_lines().map_while(Result::ok) {
@@ -48,6 +185,16 @@ fn main() {
             .collect::<Vec<_>>();
         reports.push(report);
     }
-    println!("{:?}", count_safe(&reports, is_safe));
-    println!("{:?}", count_safe(&reports, is_safe_dampened));
+    let rules = Rules {
+        max_step: util::get_flag_value("--max-step").map_or(3, |s| s.parse().unwrap()),
+        tolerance: util::get_flag_value("--tolerance").map_or(1, |s| s.parse().unwrap()),
+    };
+    if util::has_flag("--explain") {
+        explain(&reports, &rules);
+    }
+    println!("{:?}", count_safe(&reports, |r| is_safe(r, &rules)));
+    println!(
+        "{:?}",
+        count_safe(&reports, |r| is_safe_dampened(r, &rules))
+    );
 }
@@ -1,79 +1,561 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use aoc_2024::util;
-use regex::Regex;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::io::{self, BufRead, Read, Write};
+use std::num::NonZeroUsize;
+use std::ops::Range;
 use std::str::FromStr;
-use std::sync::LazyLock;
 use std::vec::Vec;
 
-#[derive(Debug)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Func {
+    Add,
+    Sub,
+    Mul,
+}
+
+impl Func {
+    fn name(self) -> &'static str {
+        match self {
+            Func::Add => "add",
+            Func::Sub => "sub",
+            Func::Mul => "mul",
+        }
+    }
+
+    fn apply(self, lhs: i64, rhs: i64) -> Option<i64> {
+        match self {
+            Func::Add => lhs.checked_add(rhs),
+            Func::Sub => lhs.checked_sub(rhs),
+            Func::Mul => lhs.checked_mul(rhs),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Expr {
+    Num(i64),
+    Var(String),
+    Call(Func, Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    fn eval(&self, variables: &HashMap<String, i64>) -> Result<i64> {
+        match self {
+            Expr::Num(n) => Ok(*n),
+            Expr::Var(name) => variables
+                .get(name)
+                .copied()
+                .ok_or_else(|| anyhow!("undefined variable `{}`", name)),
+            Expr::Call(func, lhs, rhs) => {
+                let (lhs, rhs) = (lhs.eval(variables)?, rhs.eval(variables)?);
+                func.apply(lhs, rhs)
+                    .ok_or_else(|| anyhow!("overflow in {}({},{})", func.name(), lhs, rhs))
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 enum Ops {
-    Mul(u64, u64),
+    // A top-level call, whose result is added to the total.
+    Eval(Expr),
+    Set(String, Expr),
     Disable,
     Enable,
 }
 
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Expr::Num(n) => write!(f, "{}", n),
+            Expr::Var(name) => write!(f, "{}", name),
+            Expr::Call(func, lhs, rhs) => write!(f, "{}({},{})", func.name(), lhs, rhs),
+        }
+    }
+}
+
+impl fmt::Display for Ops {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Ops::Eval(expr) => write!(f, "{}", expr),
+            Ops::Set(name, expr) => write!(f, "set({},{})", name, expr),
+            Ops::Disable => write!(f, "don't()"),
+            Ops::Enable => write!(f, "do()"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Instruction {
+    op: Ops,
+    // Byte range of the instruction in the original memory.
+    span: Range<usize>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Language {
+    // Only `mul(a,b)` of two literals, `do()` and `don't()`, as in the puzzle.
+    Puzzle,
+    // Also `add` and `sub`, nested calls, variables, and `set(name,expr)`. A name only counts as
+    // a variable once an earlier `set` has assigned it; otherwise it's just corruption.
+    Extended,
+}
+
+// Why a parse attempt didn't produce a value.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum NoParse {
+    Fail,
+    // The input ended mid-instruction; more bytes might complete it.
+    Incomplete,
+}
+
+type Parse<T> = std::result::Result<T, NoParse>;
+
+// The most calls that can be nested inside one another. Anything deeper is treated as
+// corruption, so deeply nested junk can't overflow the stack.
+const MAX_DEPTH: usize = 64;
+
+// Recursive-descent parser for a single instruction at the start of `input`.
+struct Parser<'a> {
+    input: &'a [u8],
+    pos: usize,
+    language: Language,
+    names: &'a HashSet<String>,
+    // How many calls deep the parser is.
+    depth: usize,
+    // Where the first call nested more than MAX_DEPTH deep starts, if parsing reached one.
+    too_deep: Option<usize>,
+}
+
+impl<'a> Parser<'a> {
+    fn new(input: &'a [u8], language: Language, names: &'a HashSet<String>) -> Self {
+        Self {
+            input,
+            pos: 0,
+            language,
+            names,
+            depth: 0,
+            too_deep: None,
+        }
+    }
+
+    // Run `f`, rewinding on failure so an alternative can be tried from the same spot.
+    fn attempt<T>(&mut self, f: impl FnOnce(&mut Self) -> Parse<T>) -> Parse<T> {
+        let start = self.pos;
+        let res = f(self);
+        if res.is_err() {
+            self.pos = start;
+        }
+        res
+    }
+
+    fn literal(&mut self, lit: &str) -> Parse<()> {
+        let rest = &self.input[self.pos..];
+        if rest.starts_with(lit.as_bytes()) {
+            self.pos += lit.len();
+            Ok(())
+        } else if lit.as_bytes().starts_with(rest) {
+            Err(NoParse::Incomplete)
+        } else {
+            Err(NoParse::Fail)
+        }
+    }
+
+    // Consume the longest run of bytes matching `pred`, which must be non-empty.
+    fn take_while(&mut self, pred: impl Fn(u8) -> bool) -> Parse<&'a str> {
+        let rest = &self.input[self.pos..];
+        let len = rest.iter().take_while(|&&c| pred(c)).count();
+        if len == rest.len() {
+            // Whatever comes next might extend the run.
+            return Err(NoParse::Incomplete);
+        }
+        if len == 0 {
+            return Err(NoParse::Fail);
+        }
+        self.pos += len;
+        // Safe to unwrap: the run is all ASCII.
+        Ok(std::str::from_utf8(&rest[..len]).unwrap())
+    }
+
+    fn number(&mut self) -> Parse<i64> {
+        self.take_while(|c| c.is_ascii_digit())?
+            .parse()
+            .map_err(|_| NoParse::Fail)
+    }
+
+    fn ident(&mut self) -> Parse<String> {
+        Ok(self
+            .take_while(|c| c.is_ascii_lowercase() || c == b'_')?
+            .to_string())
+    }
+
+    fn expr(&mut self) -> Parse<Expr> {
+        if self.language == Language::Puzzle {
+            return self.number().map(Expr::Num);
+        }
+        match self.attempt(Self::call) {
+            Err(NoParse::Fail) => {}
+            res => return res,
+        }
+        match self.attempt(Self::number) {
+            Err(NoParse::Fail) => {}
+            res => return res.map(Expr::Num),
+        }
+        let name = self.ident()?;
+        if !self.names.contains(&name) {
+            return Err(NoParse::Fail);
+        }
+        Ok(Expr::Var(name))
+    }
+
+    // Run `f` to parse the arguments of the call starting at `start`, failing instead if that
+    // call is nested too deeply.
+    fn nested<T>(&mut self, start: usize, f: impl FnOnce(&mut Self) -> Parse<T>) -> Parse<T> {
+        if self.depth == MAX_DEPTH {
+            self.too_deep.get_or_insert(start);
+            return Err(NoParse::Fail);
+        }
+        self.depth += 1;
+        let res = f(self);
+        self.depth -= 1;
+        res
+    }
+
+    fn call(&mut self) -> Parse<Expr> {
+        let funcs: &[Func] = match self.language {
+            Language::Puzzle => &[Func::Mul],
+            Language::Extended => &[Func::Add, Func::Sub, Func::Mul],
+        };
+        let start = self.pos;
+        for &func in funcs {
+            let res = self.attempt(|p| {
+                p.literal(func.name())?;
+                p.literal("(")?;
+                p.nested(start, |p| {
+                    let lhs = p.expr()?;
+                    p.literal(",")?;
+                    let rhs = p.expr()?;
+                    p.literal(")")?;
+                    Ok(Expr::Call(func, Box::new(lhs), Box::new(rhs)))
+                })
+            });
+            if res != Err(NoParse::Fail) {
+                return res;
+            }
+        }
+        Err(NoParse::Fail)
+    }
+
+    fn instruction(&mut self) -> Parse<Ops> {
+        match self.attempt(|p| p.literal("do()")) {
+            Err(NoParse::Fail) => {}
+            res => return res.map(|_| Ops::Enable),
+        }
+        match self.attempt(|p| p.literal("don't()")) {
+            Err(NoParse::Fail) => {}
+            res => return res.map(|_| Ops::Disable),
+        }
+        if self.language == Language::Extended {
+            let res = self.attempt(|p| {
+                p.literal("set(")?;
+                let name = p.ident()?;
+                p.literal(",")?;
+                let value = p.expr()?;
+                p.literal(")")?;
+                Ok(Ops::Set(name, value))
+            });
+            if res != Err(NoParse::Fail) {
+                return res;
+            }
+        }
+        self.call().map(Ops::Eval)
+    }
+}
+
+// Finds instructions in memory that arrives a chunk at a time. An instruction cut off by the end
+// of a chunk is held back until the next chunk either completes it or rules it out.
+struct Scanner {
+    language: Language,
+    // The variables assigned so far.
+    names: HashSet<String>,
+    // Bytes not yet scanned, held back from the previous chunk.
+    pending: Vec<u8>,
+    // Offset of `pending[0]` in the whole memory.
+    offset: usize,
+}
+
+impl Scanner {
+    fn new(language: Language, names: HashSet<String>) -> Self {
+        Self {
+            language,
+            names,
+            pending: Vec::new(),
+            offset: 0,
+        }
+    }
+
+    // Scan `chunk`, skipping over the corrupted bytes between instructions. `at_eof` means
+    // nothing more is coming, so an incomplete instruction is just corruption.
+    fn feed(&mut self, chunk: &[u8], at_eof: bool) -> Vec<Instruction> {
+        self.pending.extend_from_slice(chunk);
+        let mut out = Vec::new();
+        let mut pos = 0;
+        while pos < self.pending.len() {
+            let mut parser = Parser::new(&self.pending[pos..], self.language, &self.names);
+            let parsed = parser.instruction();
+            let len = parser.pos;
+            match parsed {
+                Ok(op) => {
+                    if let Ops::Set(name, _) = &op {
+                        self.names.insert(name.clone());
+                    }
+                    let start = self.offset + pos;
+                    out.push(Instruction {
+                        op,
+                        span: start..start + len,
+                    });
+                    pos += len;
+                }
+                Err(NoParse::Incomplete) if !at_eof => break,
+                // Every call around one nested too deeply fails, so rather than descending to it
+                // again from each byte before it, carry on from the call that was too deep.
+                Err(_) => pos += parser.too_deep.unwrap_or(1),
+            }
+        }
+        self.pending.drain(..pos);
+        self.offset += pos;
+        out
+    }
+}
+
+// Find every instruction in `memory`. `names` holds the variables assigned so far, and is updated
+// with any new ones.
+fn scan(memory: &str, language: Language, names: &mut HashSet<String>) -> Vec<Instruction> {
+    let mut scanner = Scanner::new(language, std::mem::take(names));
+    let out = scanner.feed(memory.as_bytes(), true);
+    *names = scanner.names;
+    out
+}
+
+// Execution state: whether calls are enabled, and the values of variables.
 #[derive(Debug)]
-struct Computer {
-    ops: Vec<Ops>,
+struct Machine {
     enabled: bool,
+    variables: HashMap<String, i64>,
+}
+
+impl Machine {
+    fn new() -> Self {
+        Self {
+            enabled: true,
+            variables: HashMap::new(),
+        }
+    }
+
+    // Run one instruction, returning the value of a top-level call. Calls are evaluated even
+    // while disabled; it's up to the caller whether to count them.
+    fn step(&mut self, instruction: &Instruction) -> Result<Option<i64>> {
+        let eval = |expr: &Expr, variables: &HashMap<String, i64>| {
+            expr.eval(variables)
+                .map_err(|e| anyhow!("{} at bytes {:?}", e, instruction.span))
+        };
+        match &instruction.op {
+            Ops::Eval(expr) => return eval(expr, &self.variables).map(Some),
+            Ops::Set(name, expr) => {
+                let val = eval(expr, &self.variables)?;
+                self.variables.insert(name.clone(), val);
+            }
+            Ops::Disable => self.enabled = false,
+            Ops::Enable => self.enabled = true,
+        }
+        Ok(None)
+    }
+}
+
+fn add_to_total(total: i64, val: i64, span: &Range<usize>) -> Result<i64> {
+    total
+        .checked_add(val)
+        .ok_or_else(|| anyhow!("total overflowed at bytes {:?}", span))
+}
+
+#[derive(Debug)]
+struct Computer {
+    ops: Vec<Instruction>,
+    // Every variable name assigned by `ops`.
+    names: HashSet<String>,
+    machine: Machine,
     pub ignore_disable: bool,
 }
 
 impl Computer {
-    fn new(ops: Vec<Ops>) -> Self {
+    fn new(ops: Vec<Instruction>, names: HashSet<String>) -> Self {
         Self {
             ops,
-            enabled: true,
+            names,
+            machine: Machine::new(),
             ignore_disable: false,
         }
     }
 
-    fn evaluate(&mut self) -> u64 {
+    fn parse(s: &str, language: Language) -> Self {
+        let mut names = HashSet::new();
+        let ops = scan(s, language, &mut names);
+        Computer::new(ops, names)
+    }
+
+    fn evaluate(&mut self) -> Result<i64> {
+        self.machine = Machine::new();
+        self.evaluate_from(0)
+    }
+
+    // Run the ops from index `start` onwards, keeping whatever state earlier ops left behind.
+    fn evaluate_from(&mut self, start: usize) -> Result<i64> {
         let mut out = 0;
-        for op in &self.ops {
-            match op {
-                Ops::Mul(lhs, rhs) => {
-                    if self.enabled || self.ignore_disable {
-                        out += lhs * rhs
-                    }
+        for instruction in &self.ops[start..] {
+            // Disabled calls are skipped rather than evaluated, as in the puzzle.
+            if matches!(instruction.op, Ops::Eval(_))
+                && !(self.machine.enabled || self.ignore_disable)
+            {
+                continue;
+            }
+            if let Some(val) = self.machine.step(instruction)? {
+                out = add_to_total(out, val, &instruction.span)?;
+            }
+        }
+        Ok(out)
+    }
+
+    fn print_spans(&self) {
+        for instruction in &self.ops {
+            println!("{:?}: {}", instruction.span, instruction.op);
+        }
+    }
+
+    // Read snippets from stdin and evaluate each against the state left by the loaded memory.
+    fn repl(&mut self) -> Result<()> {
+        self.evaluate()?;
+        let stdin = io::stdin();
+        let mut lines = stdin.lock().lines();
+        loop {
+            print!("> ");
+            io::stdout().flush()?;
+            let Some(line) = lines.next() else {
+                println!();
+                return Ok(());
+            };
+            let start = self.ops.len();
+            self.ops
+                .extend(scan(&line?, Language::Extended, &mut self.names));
+            if self.ops.len() == start {
+                println!("no instructions found");
+                continue;
+            }
+            match self.evaluate_from(start) {
+                Ok(val) if self.machine.enabled => println!("{}", val),
+                Ok(val) => println!("{} (now disabled)", val),
+                Err(e) => println!("error: {}", e),
+            }
+        }
+    }
+}
+
+const DEFAULT_CHUNK_SIZE: NonZeroUsize = NonZeroUsize::new(64 * 1024).unwrap();
+
+// Evaluate memory read from `reader` `chunk_size` bytes at a time, without ever holding all of
+// it, returning the totals for both parts from a single pass.
+fn evaluate_stream(
+    mut reader: impl Read,
+    language: Language,
+    chunk_size: NonZeroUsize,
+) -> Result<(i64, i64)> {
+    let mut scanner = Scanner::new(language, HashSet::new());
+    let mut machine = Machine::new();
+    let mut chunk = vec![0; chunk_size.get()];
+    let (mut all, mut enabled) = (0, 0);
+    loop {
+        let read = match reader.read(&mut chunk) {
+            Ok(read) => read,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        };
+        let at_eof = read == 0;
+        for instruction in scanner.feed(&chunk[..read], at_eof) {
+            if let Some(val) = machine.step(&instruction)? {
+                all = add_to_total(all, val, &instruction.span)?;
+                if machine.enabled {
+                    enabled = add_to_total(enabled, val, &instruction.span)?;
                 }
-                Ops::Disable => self.enabled = false,
-                Ops::Enable => self.enabled = true,
             }
         }
-        out
+        if at_eof {
+            return Ok((all, enabled));
+        }
     }
 }
 
 impl FromStr for Computer {
     type Err = anyhow::Error;
     fn from_str(s: &str) -> Result<Self> {
-        static RE: LazyLock<Regex> = LazyLock::new(|| {
-            Regex::new(r"mul\((?<lhs>\d+),(?<rhs>\d+)\)|do\(\)|don't\(\)").unwrap()
-        });
-        let mut out = Vec::new();
-        for captures in RE.captures_iter(s) {
-            let full = captures.get(0).unwrap().as_str();
-            if full == "don't()" {
-                out.push(Ops::Disable);
-            } else if full == "do()" {
-                out.push(Ops::Enable);
-            } else {
-                out.push(Ops::Mul(
-                    captures["lhs"].parse::<u64>()?,
-                    captures["rhs"].parse::<u64>()?,
-                ));
-            }
-        }
-        Ok(Computer::new(out))
+        Ok(Computer::parse(s, Language::Puzzle))
     }
 }
 
 fn main() {
+    if util::has_flag("--stream") {
+        let language = if util::has_flag("--extended") {
+            Language::Extended
+        } else {
+            Language::Puzzle
+        };
+        // A zero-byte buffer would read nothing, which looks just like the end of the input.
+        let chunk_size = util::get_flag_value("--chunk-size").map_or(DEFAULT_CHUNK_SIZE, |s| {
+            s.parse()
+                .expect("--chunk-size takes a positive number of bytes")
+        });
+        let (pt1, pt2) = evaluate_stream(util::open_input(), language, chunk_size).unwrap();
+        println!("evaluated pt 1: {}", pt1);
+        println!("evaluated pt 2: {}", pt2);
+        return;
+    }
     let contents = util::get_all_input();
-    let mut parsed = contents.parse::<Computer>().unwrap();
+    let mut parsed = if util::has_flag("--extended") || util::has_flag("--repl") {
+        Computer::parse(&contents, Language::Extended)
+    } else {
+        contents.parse::<Computer>().unwrap()
+    };
+    if util::has_flag("--spans") {
+        parsed.print_spans();
+    }
+    if util::has_flag("--repl") {
+        parsed.repl().unwrap();
+        return;
+    }
     parsed.ignore_disable = true;
-    println!("evaluated pt 1: {}", parsed.evaluate());
+    println!("evaluated pt 1: {}", parsed.evaluate().unwrap());
     parsed.ignore_disable = false;
-    println!("evaluated pt 2: {}", parsed.evaluate());
+    println!("evaluated pt 2: {}", parsed.evaluate().unwrap());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn values(memory: &str) -> Vec<i64> {
+        let mut machine = Machine::new();
+        scan(memory, Language::Extended, &mut HashSet::new())
+            .iter()
+            .filter_map(|instruction| machine.step(instruction).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn nesting_depth() {
+        let nest = |depth: usize| format!("{}1{}", "add(1,".repeat(depth), ")".repeat(depth));
+        assert_eq!(values(&nest(MAX_DEPTH)), [MAX_DEPTH as i64 + 1]);
+        // Only the call that was too deep is left once the ones around it fail.
+        assert_eq!(values(&nest(MAX_DEPTH + 1)), [2]);
+        assert!(values(&"mul(".repeat(200_000)).is_empty());
+    }
 }
@@ -1,6 +1,10 @@
+use aho_corasick::AhoCorasick;
 use aoc_2024::util;
+use std::collections::HashSet;
+use std::fmt;
+use std::fs::read_to_string;
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 struct Transform {
     delta_i: i64,
     delta_j: i64,
@@ -11,110 +15,330 @@ impl Transform {
     const fn new(delta_i: i64, delta_j: i64) -> Self {
         Self { delta_i, delta_j }
     }
+
+    fn apply(self, (i, j): (usize, usize), steps: usize) -> (i64, i64) {
+        (
+            i as i64 + steps as i64 * self.delta_i,
+            j as i64 + steps as i64 * self.delta_j,
+        )
+    }
+}
+
+impl fmt::Display for Transform {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let vertical = match self.delta_i {
+            -1 => "N",
+            1 => "S",
+            _ => "",
+        };
+        let horizontal = match self.delta_j {
+            -1 => "W",
+            1 => "E",
+            _ => "",
+        };
+        write!(f, "{}{}", vertical, horizontal)
+    }
+}
+
+static TRANSFORMS: [Transform; 8] = [
+    Transform::new(-1, -1),
+    Transform::new(-1, 0),
+    Transform::new(-1, 1),
+    Transform::new(0, -1),
+    Transform::new(0, 1),
+    Transform::new(1, -1),
+    Transform::new(1, 0),
+    Transform::new(1, 1),
+];
+
+fn in_bounds(haystack: &[Vec<char>], (i, j): (i64, i64)) -> Option<(usize, usize)> {
+    let i = usize::try_from(i).ok()?;
+    let j = usize::try_from(j).ok()?;
+    (i < haystack.len() && j < haystack[i].len()).then_some((i, j))
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct WordMatch {
+    // Index into the list of words searched for.
+    word: usize,
+    start: (usize, usize),
+    direction: Transform,
 }
 
-fn count_matches_at(haystack: &[Vec<char>], needle: &[char], i: usize, j: usize) -> usize {
-    static TRANSFORMS: [Transform; 8] = [
-        Transform::new(-1, -1),
-        Transform::new(-1, 0),
-        Transform::new(-1, 1),
-        Transform::new(0, -1),
-        Transform::new(0, 1),
-        Transform::new(1, -1),
-        Transform::new(1, 0),
-        Transform::new(1, 1),
-    ];
-    if needle[0] != haystack[i][j] {
-        return 0;
-    }
-    let mut matches = 0;
-    for transform in TRANSFORMS.iter() {
-        let extent_i: Result<usize, _> =
-            (i as i64 + (needle.len() - 1) as i64 * transform.delta_i).try_into();
-        match extent_i {
-            Ok(extent) => {
-                if extent >= haystack.len() {
+// Find every occurrence of every word, reading in any of the 8 directions.
+//
+// Rather than trying each word at each cell, walk every line through the grid in each direction
+// (rows, columns and diagonals, both ways) and run all the words over it at once with
+// Aho-Corasick.
+fn find_words(haystack: &[Vec<char>], words: &[&str]) -> Vec<WordMatch> {
+    let searcher = AhoCorasick::new(words).unwrap();
+    let mut matches = Vec::new();
+    for &direction in TRANSFORMS.iter() {
+        for (i, row) in haystack.iter().enumerate() {
+            for j in 0..row.len() {
+                // Only start a line at a cell that has no predecessor in this direction.
+                let prev = Transform::new(-direction.delta_i, -direction.delta_j).apply((i, j), 1);
+                if in_bounds(haystack, prev).is_some() {
                     continue;
                 }
-            }
-            Err(_) => continue,
-        }
-        let extent_j: Result<usize, _> =
-            (j as i64 + (needle.len() - 1) as i64 * transform.delta_j).try_into();
-        match extent_j {
-            Ok(extent) => {
-                if extent >= haystack[0].len() {
-                    continue;
+                let mut line = String::new();
+                // For each byte of `line`, the number of cells along the line it came from.
+                let mut byte_to_step = Vec::new();
+                let mut step = 0;
+                while let Some((cell_i, cell_j)) =
+                    in_bounds(haystack, direction.apply((i, j), step))
+                {
+                    let c = haystack[cell_i][cell_j];
+                    line.push(c);
+                    byte_to_step.extend(std::iter::repeat_n(step, c.len_utf8()));
+                    step += 1;
+                }
+                for m in searcher.find_overlapping_iter(&line) {
+                    let (start_i, start_j) = direction.apply((i, j), byte_to_step[m.start()]);
+                    matches.push(WordMatch {
+                        word: m.pattern().as_usize(),
+                        start: (start_i as usize, start_j as usize),
+                        direction,
+                    });
                 }
             }
-            Err(_) => continue,
-        }
-        if needle.iter().enumerate().all(|(k, &c)| {
-            // safe because of earlier check
-            let new_i = (i as i64 + k as i64 * transform.delta_i) as usize;
-            let new_j = (j as i64 + k as i64 * transform.delta_j) as usize;
-            haystack[new_i][new_j] == c
-        }) {
-            matches += 1;
         }
     }
     matches
 }
 
-fn count_matches(haystack: &[Vec<char>], needle: &[char]) -> usize {
-    let mut matches = 0;
+impl WordMatch {
+    // The cells covered by this match of a word `len` letters long.
+    fn cells(&self, len: usize) -> impl Iterator<Item = (usize, usize)> + '_ {
+        (0..len).map(|step| {
+            let (i, j) = self.direction.apply(self.start, step);
+            (i as usize, j as usize)
+        })
+    }
+}
+
+fn count_matches(haystack: &[Vec<char>], needle: &str) -> usize {
+    find_words(haystack, &[needle]).len()
+}
 
-    for (i, row) in haystack.iter().enumerate() {
-        for (j, ch) in row.iter().enumerate() {
-            if *ch == needle[0] {
-                let new_matches = count_matches_at(haystack, needle, i, j);
-                matches += new_matches;
+// A 2D template of letters. Cells not in `cells` (or given as '.' in a text template) match
+// anything.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Shape {
+    cells: Vec<((usize, usize), char)>,
+}
+
+impl Shape {
+    fn new(cells: impl IntoIterator<Item = ((usize, usize), char)>) -> Self {
+        let mut cells = cells.into_iter().collect::<Vec<_>>();
+        // Normalize so the shape's bounding box starts at (0, 0).
+        let min_i = cells.iter().map(|&((i, _), _)| i).min().unwrap_or(0);
+        let min_j = cells.iter().map(|&((_, j), _)| j).min().unwrap_or(0);
+        for ((i, j), _) in cells.iter_mut() {
+            *i -= min_i;
+            *j -= min_j;
+        }
+        cells.sort();
+        cells.dedup();
+        Self { cells }
+    }
+
+    // Parse a template given as rows of text, e.g. "M.S\n.A.\nM.S".
+    fn parse(template: &str) -> Self {
+        Shape::new(template.lines().enumerate().flat_map(|(i, row)| {
+            row.chars()
+                .enumerate()
+                .filter(|&(_, c)| c != '.')
+                .map(move |(j, c)| ((i, j), c))
+        }))
+    }
+
+    // `word` written down both diagonals of a square, crossing in the middle.
+    fn x(word: &str) -> Self {
+        assert!(word.chars().count() % 2 == 1, "X needs an odd-length word");
+        let len = word.chars().count();
+        Shape::new(
+            word.chars()
+                .enumerate()
+                .flat_map(|(k, c)| [((k, k), c), ((k, len - 1 - k), c)]),
+        )
+    }
+
+    // `word` written across and down the middle of a square, crossing in the middle.
+    fn plus(word: &str) -> Self {
+        assert!(
+            word.chars().count() % 2 == 1,
+            "plus needs an odd-length word"
+        );
+        let mid = word.chars().count() / 2;
+        Shape::new(
+            word.chars()
+                .enumerate()
+                .flat_map(|(k, c)| [((mid, k), c), ((k, mid), c)]),
+        )
+    }
+
+    // `word` written down to its middle letter, then right from there.
+    fn l(word: &str) -> Self {
+        let corner = word.chars().count() / 2;
+        Shape::new(word.chars().enumerate().map(|(k, c)| {
+            if k <= corner {
+                ((k, 0), c)
+            } else {
+                ((corner, k - corner), c)
+            }
+        }))
+    }
+
+    // Rotate a quarter turn clockwise.
+    fn rotate(&self) -> Self {
+        let height = self.cells.iter().map(|&((i, _), _)| i).max().unwrap_or(0);
+        Shape::new(self.cells.iter().map(|&((i, j), c)| ((j, height - i), c)))
+    }
+
+    // Flip left to right.
+    fn mirror(&self) -> Self {
+        let width = self.cells.iter().map(|&((_, j), _)| j).max().unwrap_or(0);
+        Shape::new(self.cells.iter().map(|&((i, j), c)| ((i, width - j), c)))
+    }
+
+    // All distinct rotations and reflections of this shape, along with whether each was mirrored
+    // and the number of quarter turns it took after that, so symmetric shapes aren't matched twice.
+    fn orientations(&self) -> Vec<(bool, usize, Self)> {
+        let mut out: Vec<(bool, usize, Self)> = Vec::new();
+        for (mirrored, mut shape) in [(false, self.clone()), (true, self.mirror())] {
+            for turns in 0..4 {
+                if !out.iter().any(|(_, _, s)| *s == shape) {
+                    out.push((mirrored, turns, shape.clone()));
+                }
+                shape = shape.rotate();
             }
         }
+        out
     }
 
-    matches
+    fn matches_at(&self, haystack: &[Vec<char>], (i, j): (usize, usize)) -> bool {
+        self.cells.iter().all(|&((di, dj), c)| {
+            haystack
+                .get(i + di)
+                .and_then(|row| row.get(j + dj))
+                .is_some_and(|&actual| actual == c)
+        })
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct ShapeMatch {
+    top_left: (usize, usize),
+    // Whether the template was mirrored left to right (before rotating).
+    mirrored: bool,
+    // Number of clockwise quarter turns applied to the template.
+    rotation: usize,
 }
 
-fn is_x_match_at(haystack: &[Vec<char>], needle: &[char], i: usize, j: usize) -> bool {
-    static TRANSFORM_SETS: [[Transform; 2]; 2] = [
-        [Transform::new(-1, -1), Transform::new(1, 1)],
-        [Transform::new(-1, 1), Transform::new(1, -1)],
-    ];
-    if needle[1] != haystack[i][j] {
-        return false;
-    }
-    for transforms in TRANSFORM_SETS.iter() {
-        let actual = [
-            haystack[(transforms[0].delta_i + i as i64) as usize]
-                [(transforms[0].delta_j + j as i64) as usize],
-            haystack[(transforms[1].delta_i + i as i64) as usize]
-                [(transforms[1].delta_j + j as i64) as usize],
-        ];
-        let expected = [needle[0], needle[2]];
-        let expected_rev = [needle[2], needle[0]];
-
-        if actual != expected && actual != expected_rev {
-            return false;
+// Find every placement of `shape`, in any rotation or reflection, in the grid.
+fn find_shapes(haystack: &[Vec<char>], shape: &Shape) -> Vec<ShapeMatch> {
+    let mut matches = Vec::new();
+    for (mirrored, rotation, oriented) in shape.orientations() {
+        for (i, row) in haystack.iter().enumerate() {
+            for j in 0..row.len() {
+                if oriented.matches_at(haystack, (i, j)) {
+                    matches.push(ShapeMatch {
+                        top_left: (i, j),
+                        mirrored,
+                        rotation,
+                    });
+                }
+            }
         }
     }
-    true
+    matches
+}
+
+fn count_x_matches(haystack: &[Vec<char>], needle: &str) -> usize {
+    find_shapes(haystack, &Shape::x(needle)).len()
 }
 
-fn count_x_matches(haystack: &[Vec<char>], needle: &[char]) -> usize {
-    let mut matches = 0;
-    assert!(needle.len() == 3);
+const COLORS: [&str; 6] = [
+    "\x1b[1;31m",
+    "\x1b[1;32m",
+    "\x1b[1;33m",
+    "\x1b[1;34m",
+    "\x1b[1;35m",
+    "\x1b[1;36m",
+];
+const DIM: &str = "\x1b[2m";
+const RESET: &str = "\x1b[0m";
 
-    // Skip first and last row; an X won't be centered there.
-    for (i, row) in haystack.iter().enumerate().skip(1).take(haystack.len() - 2) {
-        for (j, ch) in row.iter().enumerate().skip(1).take(row.len() - 2) {
-            if *ch == needle[1] && is_x_match_at(haystack, needle, i, j) {
-                matches += 1;
+// Print the grid with each matched cell colored by the (first) word covering it, optionally
+// dimming the letters no match uses.
+fn print_highlighted(haystack: &[Vec<char>], words: &[&str], matches: &[WordMatch], dim: bool) {
+    let mut colors = haystack
+        .iter()
+        .map(|row| vec![None; row.len()])
+        .collect::<Vec<_>>();
+    for m in matches.iter() {
+        for (i, j) in m.cells(words[m.word].chars().count()) {
+            let color = &mut colors[i][j];
+            *color = Some(color.map_or(m.word, |w: usize| w.min(m.word)));
+        }
+    }
+    for (row, row_colors) in haystack.iter().zip(colors.iter()) {
+        for (c, color) in row.iter().zip(row_colors.iter()) {
+            match color {
+                Some(word) => print!("{}{}{}", COLORS[word % COLORS.len()], c, RESET),
+                None if dim => print!("{}{}{}", DIM, c, RESET),
+                None => print!("{}", c),
             }
         }
+        println!();
     }
+    println!();
+}
 
-    matches
+fn print_word_matches(words: &[&str], matches: &[WordMatch]) {
+    for m in matches.iter() {
+        println!("{} at {:?} going {}", words[m.word], m.start, m.direction);
+    }
+    for (idx, word) in words.iter().enumerate() {
+        let count = matches.iter().filter(|m| m.word == idx).count();
+        println!("{}: {} matches", word, count);
+    }
+}
+
+// Parse a shape given on the command line: "x:WORD", "plus:WORD", "l:WORD", or a path to a file
+// holding a text template.
+fn parse_shape_arg(arg: &str) -> Shape {
+    match arg.split_once(':') {
+        Some(("x", word)) => Shape::x(word),
+        Some(("plus", word)) => Shape::plus(word),
+        Some(("l", word)) => Shape::l(word),
+        _ => Shape::parse(
+            &read_to_string(arg).unwrap_or_else(|_| panic!("Could not read shape {}", arg)),
+        ),
+    }
+}
+
+fn print_shape_matches(haystack: &[Vec<char>], shape: &Shape) {
+    let matches = find_shapes(haystack, shape);
+    let distinct = matches
+        .iter()
+        .map(|m| m.top_left)
+        .collect::<HashSet<_>>()
+        .len();
+    for m in matches.iter() {
+        println!(
+            "shape at {:?}, {}rotated {} quarter turns",
+            m.top_left,
+            if m.mirrored { "mirrored, " } else { "" },
+            m.rotation
+        );
+    }
+    println!(
+        "shape: {} matches at {} distinct positions",
+        matches.len(),
+        distinct
+    );
 }
 
 fn main() {
@@ -122,12 +346,48 @@ fn main() {
     for line in util::get_lines().map_while(Result::ok) {
         haystack.push(line.chars().collect::<Vec<_>>());
     }
-    println!(
-        "matches: {}",
-        count_matches(&haystack, &['X', 'M', 'A', 'S'])
-    );
-    println!(
-        "X-shape matches: {}",
-        count_x_matches(&haystack, &['M', 'A', 'S'])
-    );
+    let words = util::get_flag_value("--words");
+    if words.is_some() || util::has_flag("--highlight") {
+        let words = words
+            .as_deref()
+            .unwrap_or("XMAS")
+            .split(',')
+            // An empty word would "match" past the end of every line.
+            .filter(|word| !word.is_empty())
+            .collect::<Vec<_>>();
+        let matches = find_words(&haystack, &words);
+        if util::has_flag("--highlight") {
+            print_highlighted(&haystack, &words, &matches, util::has_flag("--dim"));
+        }
+        print_word_matches(&words, &matches);
+    }
+    if let Some(shape) = util::get_flag_value("--shape") {
+        print_shape_matches(&haystack, &parse_shape_arg(&shape));
+    }
+    println!("matches: {}", count_matches(&haystack, "XMAS"));
+    println!("X-shape matches: {}", count_x_matches(&haystack, "MAS"));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn orientations() {
+        // An L and its mirror image, the J, each in 4 rotations.
+        assert_eq!(Shape::l("ABCDE").orientations().len(), 8);
+        // Mirroring an X only gives one of its rotations.
+        assert_eq!(Shape::x("MAS").orientations().len(), 4);
+        assert_eq!(Shape::plus("AAA").orientations().len(), 1);
+
+        let grid = ["..A", "..B", "EDC"].map(|row| row.chars().collect::<Vec<_>>());
+        assert_eq!(
+            find_shapes(&grid, &Shape::l("ABCDE")),
+            [ShapeMatch {
+                top_left: (0, 0),
+                mirrored: true,
+                rotation: 0,
+            }]
+        );
+    }
 }
@@ -1,6 +1,125 @@
+use anyhow::{bail, Result};
 use aoc_2024::util;
+use std::cmp::Ordering;
 use std::collections::{HashMap, HashSet, VecDeque};
 
+// The rules as a dense bit matrix, so checking a pair is a single lookup. Pages are numbered in
+// order of first appearance, so the matrix is only as big as the number of distinct pages.
+struct RuleMatrix {
+    index: HashMap<usize, usize>,
+    words_per_row: usize,
+    bits: Vec<u64>,
+}
+
+impl RuleMatrix {
+    fn new(rules: &HashMap<usize, HashSet<usize>>) -> Self {
+        let mut index = HashMap::new();
+        for page in rules
+            .iter()
+            .flat_map(|(before, after)| after.iter().chain([before]))
+        {
+            let next = index.len();
+            index.entry(*page).or_insert(next);
+        }
+        let size = index.len();
+        let words_per_row = size.div_ceil(64);
+        let mut bits = vec![0; size * words_per_row];
+        for (before, after) in rules.iter() {
+            let row = index[before] * words_per_row;
+            for a in after.iter().map(|a| index[a]) {
+                bits[row + a / 64] |= 1 << (a % 64);
+            }
+        }
+        Self {
+            index,
+            words_per_row,
+            bits,
+        }
+    }
+
+    // Whether some rule says `before` must come before `after`.
+    fn must_precede(&self, before: usize, after: usize) -> bool {
+        match (self.index.get(&before), self.index.get(&after)) {
+            (Some(&before), Some(&after)) => {
+                self.bits[before * self.words_per_row + after / 64] & (1 << (after % 64)) != 0
+            }
+            _ => false,
+        }
+    }
+
+    fn compare(&self, a: usize, b: usize) -> Ordering {
+        if self.must_precede(a, b) {
+            Ordering::Less
+        } else if self.must_precede(b, a) {
+            Ordering::Greater
+        } else {
+            Ordering::Equal
+        }
+    }
+
+    // Whether no page in `job` comes after one that must follow it.
+    fn obeys(&self, job: &[usize]) -> bool {
+        job.iter()
+            .enumerate()
+            .all(|(i, &a)| job[..i].iter().all(|&b| !self.must_precede(a, b)))
+    }
+
+    // Sort `job` so it obeys the rules, taking pages that are free to go next in the order they
+    // appear in the job.
+    fn toposort(&self, job: &[usize]) -> Vec<usize> {
+        let mut incoming = job
+            .iter()
+            .map(|&a| job.iter().filter(|&&b| self.must_precede(b, a)).count())
+            .collect::<Vec<_>>();
+        let mut ready = (0..job.len())
+            .filter(|&k| incoming[k] == 0)
+            .collect::<VecDeque<_>>();
+        let mut sorted = Vec::with_capacity(job.len());
+        while let Some(k) = ready.pop_front() {
+            sorted.push(job[k]);
+            for (l, &b) in job.iter().enumerate() {
+                if self.must_precede(job[k], b) {
+                    incoming[l] -= 1;
+                    if incoming[l] == 0 {
+                        ready.push_back(l);
+                    }
+                }
+            }
+        }
+        assert_eq!(
+            sorted.len(),
+            job.len(),
+            "rules for {:?} have a cycle; run validate first",
+            job
+        );
+        sorted
+    }
+}
+
+// Merge sort `job` by `compare`. Unlike the standard library's sorts, which may panic when the
+// comparison isn't a total order, this just gives some order, which the caller checks.
+fn merge_sort_by(job: &[usize], compare: &impl Fn(usize, usize) -> Ordering) -> Vec<usize> {
+    if job.len() <= 1 {
+        return job.to_vec();
+    }
+    let (left, right) = job.split_at(job.len() / 2);
+    let (left, right) = (merge_sort_by(left, compare), merge_sort_by(right, compare));
+    let mut merged = Vec::with_capacity(job.len());
+    let (mut i, mut j) = (0, 0);
+    while i < left.len() && j < right.len() {
+        if compare(right[j], left[i]) == Ordering::Less {
+            merged.push(right[j]);
+            j += 1;
+        } else {
+            merged.push(left[i]);
+            i += 1;
+        }
+    }
+    merged.extend_from_slice(&left[i..]);
+    merged.extend_from_slice(&right[j..]);
+    merged
+}
+
 fn obeys_constraints(job: &[usize], rules: &HashMap<usize, HashSet<usize>>) -> bool {
     for (before, all_after) in rules.iter() {
         if let Some(idx) = job.iter().position(|x| x == before) {
@@ -12,8 +131,10 @@ fn obeys_constraints(job: &[usize], rules: &HashMap<usize, HashSet<usize>>) -> b
     true
 }
 
-fn toposort_get_mid(job: &[usize], rules: &HashMap<usize, HashSet<usize>>) -> usize {
+// Sort `job` so it obeys the rules, also returning whether that's the only order that does.
+fn toposort(job: &[usize], rules: &HashMap<usize, HashSet<usize>>) -> (Vec<usize>, bool) {
     let mut sorted = Vec::new();
+    let mut unique = true;
     let mut reversed = HashMap::new();
     let mut graph = HashMap::new();
     for (before, after) in rules.iter().filter(|(page, _)| job.contains(page)) {
@@ -31,6 +152,10 @@ fn toposort_get_mid(job: &[usize], rules: &HashMap<usize, HashSet<usize>>) -> us
             .cloned(),
     );
     while let Some(page) = no_incoming.pop_front() {
+        // Any of the other pages ready now could have gone first instead.
+        if !no_incoming.is_empty() {
+            unique = false;
+        }
         sorted.push(page);
         if let Some(afters) = graph.remove(&page) {
             for &neighbor in afters.iter() {
@@ -50,18 +175,229 @@ fn toposort_get_mid(job: &[usize], rules: &HashMap<usize, HashSet<usize>>) -> us
             }
         }
     }
-    sorted[sorted.len() / 2]
+    assert_eq!(
+        sorted.len(),
+        job.len(),
+        "rules for {:?} have a cycle; run validate first",
+        job
+    );
+    (sorted, unique)
+}
+
+// The fewest pages that must be moved (taken out and reinserted elsewhere) to make `job` obey
+// the rules.
+//
+// The pages left alone must have no pair out of order under the transitive closure of the rules
+// (which is also enough for the moved pages to fit around them). Call page x "ahead" of y if x
+// is later in the job but must come earlier; that relation is a partial order, and the pages
+// left alone are exactly an antichain of it. By Dilworth's theorem the largest antichain is
+// n - (size of a maximum matching in the bipartite graph of the relation), so the number of
+// moves is just that matching size.
+fn min_moves(job: &[usize], rules: &HashMap<usize, HashSet<usize>>) -> usize {
+    let len = job.len();
+    // must_precede[a][b]: job[a] must come before job[b], directly or transitively.
+    let mut must_precede = vec![vec![false; len]; len];
+    for (a, page_a) in job.iter().enumerate() {
+        if let Some(after) = rules.get(page_a) {
+            for (b, page_b) in job.iter().enumerate() {
+                must_precede[a][b] = after.contains(page_b);
+            }
+        }
+    }
+    for k in 0..len {
+        let via = must_precede[k].clone();
+        for row in must_precede.iter_mut().filter(|row| row[k]) {
+            for (reach, &via_reach) in row.iter_mut().zip(via.iter()) {
+                *reach |= via_reach;
+            }
+        }
+    }
+    let ahead = |a: usize, b: usize| a > b && must_precede[a][b];
+
+    // Kuhn's augmenting-path matching.
+    fn augment(
+        a: usize,
+        len: usize,
+        ahead: &impl Fn(usize, usize) -> bool,
+        seen: &mut [bool],
+        matched_to: &mut [Option<usize>],
+    ) -> bool {
+        for b in 0..len {
+            if ahead(a, b) && !seen[b] {
+                seen[b] = true;
+                if matched_to[b].is_none_or(|other| augment(other, len, ahead, seen, matched_to)) {
+                    matched_to[b] = Some(a);
+                    return true;
+                }
+            }
+        }
+        false
+    }
+    let mut matched_to = vec![None; len];
+    (0..len)
+        .filter(|&a| augment(a, len, &ahead, &mut vec![false; len], &mut matched_to))
+        .count()
+}
+
+fn format_job(job: &[usize]) -> String {
+    job.iter()
+        .map(|p| p.to_string())
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+// Print the corrected order for each update that breaks the rules.
+fn print_corrections(jobs: &[Vec<usize>], rules: &HashMap<usize, HashSet<usize>>) {
+    for (idx, job) in jobs.iter().enumerate() {
+        if obeys_constraints(job, rules) {
+            continue;
+        }
+        let (sorted, unique) = toposort(job, rules);
+        println!(
+            "update {}: {} -> {} ({}, {} moves)",
+            idx + 1,
+            format_job(job),
+            format_job(&sorted),
+            if unique { "unique" } else { "not unique" },
+            min_moves(job, rules)
+        );
+    }
+}
+
+// Find a cycle in the rules between `pages`, returning it as a list of pages where each must come
+// before the next (and the last before the first).
+fn find_cycle(pages: &[usize], rules: &HashMap<usize, HashSet<usize>>) -> Option<Vec<usize>> {
+    #[derive(Copy, Clone, PartialEq, Eq)]
+    enum Mark {
+        InProgress,
+        Done,
+    }
+    let in_scope = pages.iter().collect::<HashSet<_>>();
+    let successors = |page: usize| -> Vec<usize> {
+        rules
+            .get(&page)
+            .map(|after| {
+                after
+                    .iter()
+                    .filter(|a| in_scope.contains(a))
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default()
+    };
+
+    let mut marks = HashMap::new();
+    for &root in pages {
+        if marks.contains_key(&root) {
+            continue;
+        }
+        // Iterative DFS; `path` holds the pages on the stack, each with its unvisited successors.
+        let mut path = vec![(root, successors(root))];
+        marks.insert(root, Mark::InProgress);
+        while let Some((page, remaining)) = path.last_mut() {
+            let Some(next) = remaining.pop() else {
+                marks.insert(*page, Mark::Done);
+                path.pop();
+                continue;
+            };
+            match marks.get(&next) {
+                Some(Mark::Done) => {}
+                Some(Mark::InProgress) => {
+                    let start = path.iter().position(|&(p, _)| p == next).unwrap();
+                    return Some(path[start..].iter().map(|&(p, _)| p).collect());
+                }
+                None => {
+                    marks.insert(next, Mark::InProgress);
+                    path.push((next, successors(next)));
+                }
+            }
+        }
+    }
+    None
+}
+
+fn format_cycle(cycle: &[usize]) -> String {
+    cycle
+        .iter()
+        .chain(cycle.first())
+        .map(|p| p.to_string())
+        .collect::<Vec<_>>()
+        .join(" -> ")
+}
+
+// Check the rules are usable: no update may contain a cycle of rules (or there's no correct
+// order), and each update should have a well-defined middle page. A cycle in the full rule set
+// is only a warning, since updates never containing all of it are still fine.
+fn validate(jobs: &[Vec<usize>], rules: &HashMap<usize, HashSet<usize>>) -> Result<()> {
+    let mut all_pages = rules
+        .iter()
+        .flat_map(|(before, after)| after.iter().chain([before]))
+        .cloned()
+        .collect::<Vec<_>>();
+    all_pages.sort();
+    all_pages.dedup();
+    if let Some(cycle) = find_cycle(&all_pages, rules) {
+        eprintln!("warning: rule set has a cycle: {}", format_cycle(&cycle));
+    }
+
+    let mut conflicts = Vec::new();
+    for (idx, job) in jobs.iter().enumerate() {
+        if job.len() % 2 == 0 {
+            eprintln!(
+                "warning: update {} ({:?}) has an even number of pages, so no single middle page",
+                idx + 1,
+                job
+            );
+        }
+        if let Some(cycle) = find_cycle(job, rules) {
+            conflicts.push(format!(
+                "update {} ({:?}) has conflicting rules: {}",
+                idx + 1,
+                job,
+                format_cycle(&cycle)
+            ));
+        }
+    }
+    if !conflicts.is_empty() {
+        bail!("no valid ordering exists:\n{}", conflicts.join("\n"));
+    }
+    Ok(())
+}
+
+// Returns whether `job` already obeys the rules, along with its middle page once sorted.
+//
+// If every pair of neighbors in the sorted job has a rule between them, the rules put the pages
+// in a chain, so the sorted order is the only correct one and the job obeys the rules exactly
+// when it's already in that order.
+fn check_and_get_mid(job: &[usize], matrix: &RuleMatrix) -> (bool, usize) {
+    let sorted = merge_sort_by(job, &|a, b| matrix.compare(a, b));
+    if !sorted
+        .windows(2)
+        .all(|pair| matrix.must_precede(pair[0], pair[1]))
+    {
+        // Some pages are unordered relative to each other, so the sort can't be trusted.
+        if matrix.obeys(job) {
+            return (true, job[job.len() / 2]);
+        }
+        let sorted = matrix.toposort(job);
+        return (false, sorted[sorted.len() / 2]);
+    }
+    (sorted == job, sorted[sorted.len() / 2])
 }
 
 fn sums(jobs: &[Vec<usize>], rules: &HashMap<usize, HashSet<usize>>) -> (usize, usize) {
-    let (good, bad): (Vec<_>, Vec<_>) = jobs.iter().partition(|j| obeys_constraints(j, rules));
-    (
-        good.iter().map(|job| job[job.len() / 2]).sum(),
-        bad.iter().map(|j| toposort_get_mid(j, rules)).sum(),
-    )
+    let matrix = RuleMatrix::new(rules);
+    let (mut good, mut bad) = (0, 0);
+    for job in jobs.iter() {
+        match check_and_get_mid(job, &matrix) {
+            (true, mid) => good += mid,
+            (false, mid) => bad += mid,
+        }
+    }
+    (good, bad)
 }
 
-fn main() {
+fn main() -> Result<()> {
     let mut rules = HashMap::new();
     let mut saw_empty = false;
     let mut jobs = Vec::new();
@@ -85,5 +421,49 @@ fn main() {
         }
     }
 
+    validate(&jobs, &rules)?;
+    if util::has_flag("--corrected") {
+        print_corrections(&jobs, &rules);
+    }
     println!("middle sums: {:?}", sums(&jobs, &rules));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matrix_fallback_agrees() {
+        // Sparse rules, so the sorted neighbors often have no rule between them.
+        let rules = HashMap::from([
+            (1, HashSet::from([2, 5])),
+            (2, HashSet::from([3])),
+            (4, HashSet::from([3, 1000])),
+        ]);
+        let matrix = RuleMatrix::new(&rules);
+        let jobs = [
+            vec![1, 2, 3],
+            vec![3, 2, 1],
+            vec![4, 1, 3, 5, 2],
+            vec![5, 4, 3, 2, 1],
+            vec![7, 1000, 4],
+            vec![1000, 6, 4],
+        ];
+        for job in jobs.iter() {
+            assert_eq!(
+                matrix.obeys(job),
+                obeys_constraints(job, &rules),
+                "{:?}",
+                job
+            );
+            let sorted = matrix.toposort(job);
+            assert!(obeys_constraints(&sorted, &rules), "{:?}", sorted);
+            let (ok, mid) = check_and_get_mid(job, &matrix);
+            assert_eq!(ok, obeys_constraints(job, &rules), "{:?}", job);
+            if ok {
+                assert_eq!(mid, job[job.len() / 2]);
+            }
+        }
+    }
 }
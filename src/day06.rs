@@ -1,4 +1,5 @@
 use aoc_2024::util;
+use std::collections::{HashMap, HashSet};
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 enum Square {
@@ -31,58 +32,350 @@ impl Direction {
             Direction::Left => (0, -1),
         }
     }
-    fn next(&self) -> Self {
-        match self {
-            Direction::Up => Direction::Right,
-            Direction::Right => Direction::Down,
-            Direction::Down => Direction::Left,
-            Direction::Left => Direction::Up,
+    fn turn(self, rule: TurnRule) -> Self {
+        const CLOCKWISE: [Direction; 4] = [
+            Direction::Up,
+            Direction::Right,
+            Direction::Down,
+            Direction::Left,
+        ];
+        let quarter_turns = match rule {
+            TurnRule::Right => 1,
+            TurnRule::Reverse => 2,
+            TurnRule::Left => 3,
+        };
+        CLOCKWISE[(self as usize + quarter_turns) % 4]
+    }
+    fn from_marker(c: char) -> Option<Self> {
+        match c {
+            '^' => Some(Direction::Up),
+            '>' => Some(Direction::Right),
+            'v' => Some(Direction::Down),
+            '<' => Some(Direction::Left),
+            _ => None,
         }
     }
 }
 
-// Simulate, returning the set of visited squares if there was no loop.
-fn simulate(
-    row_size: usize,
+// What a guard does on hitting an obstacle.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum TurnRule {
+    Right,
+    Left,
+    Reverse,
+}
+
+// Whether guards get in each other's way.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum GuardInteraction {
+    // A guard treats any other guard still on the board as an obstacle.
+    Collide,
+    // Guards walk through each other, so each moves as if alone.
+    Ignore,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+struct Guard {
+    pos: (usize, usize),
+    direction: Direction,
+}
+
+impl Guard {
+    // The square the guard is facing, if it's still on the board.
+    fn ahead(&self, board: &[Vec<Square>]) -> Option<(usize, usize)> {
+        let (delta_i, delta_j) = self.direction.to_tuple();
+        let i = add_checked(self.pos.0, delta_i)?;
+        let j = add_checked(self.pos.1, delta_j)?;
+        (i < board.len() && j < board[i].len()).then_some((i, j))
+    }
+}
+
+// A loop: from step `start` on, the same `period` steps repeat forever.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct Cycle {
+    start: usize,
+    period: usize,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Fate {
+    // Walked off the board on the given step.
+    Exited(usize),
+    Looped(Cycle),
+}
+
+#[derive(Debug, Clone)]
+struct GuardReport {
+    start: Guard,
+    // Every square the guard stood on, in order, until it left or its loop was detected.
+    path: Vec<(usize, usize)>,
+    fate: Fate,
+}
+
+// Move all `guards` a step at a time (a turn counts as a step) until every one has left the
+// board or they're stuck in a loop. Guards move in order within a step, so with
+// `GuardInteraction::Collide` a guard sees where earlier guards have already moved to.
+//
+// A loop is only certain once the state of every guard still on the board repeats; with
+// `GuardInteraction::Ignore` each guard is simulated separately, so its own state repeating is
+// enough.
+fn simulate_guards(
     board: &[Vec<Square>],
-    guard_start: (usize, usize),
-    loop_checker: &mut [u8],
-) -> bool {
-    let mut direction = Direction::Up;
-    let ptr = loop_checker.as_mut_ptr();
-    unsafe {
-        // Safety: Writing only |len| bytes to a vector with |len| u8s
-        std::ptr::write_bytes(ptr, 0, loop_checker.len());
+    guards: &[Guard],
+    turn: TurnRule,
+    interaction: GuardInteraction,
+) -> Vec<GuardReport> {
+    if interaction == GuardInteraction::Ignore && guards.len() > 1 {
+        return guards
+            .iter()
+            .flat_map(|&guard| simulate_guards(board, &[guard], turn, interaction))
+            .collect();
     }
 
-    let (mut guard_i, mut guard_j) = guard_start;
-    loop {
-        if loop_checker[guard_i * row_size + guard_j] & (1 << (direction as usize)) != 0 {
-            return false;
-        }
-        loop_checker[guard_i * row_size + guard_j] |= 1 << (direction as usize);
-        if let (Some(next_guard_i), Some(next_guard_j)) = (
-            add_checked(guard_i, direction.to_tuple().0),
-            add_checked(guard_j, direction.to_tuple().1),
-        ) {
-            // break if we go out of bounds
-            if next_guard_i >= board.len() || next_guard_j >= row_size {
-                break;
+    let mut active = guards.iter().map(|&g| Some(g)).collect::<Vec<_>>();
+    let mut paths = guards.iter().map(|g| vec![g.pos]).collect::<Vec<_>>();
+    let mut fates = vec![None; guards.len()];
+    let mut seen = HashMap::new();
+    let mut step = 0;
+    while active.iter().any(Option::is_some) {
+        if let Some(&start) = seen.get(&active) {
+            let cycle = Cycle {
+                start,
+                period: step - start,
+            };
+            for (fate, guard) in fates.iter_mut().zip(active.iter()) {
+                if guard.is_some() {
+                    *fate = Some(Fate::Looped(cycle));
+                }
             }
-            // turn if needed
-            if board[next_guard_i][next_guard_j] == Square::Full {
-                // Don't commit this; instead turn
-                direction = direction.next();
+            break;
+        }
+        seen.insert(active.clone(), step);
+        step += 1;
+
+        for idx in 0..active.len() {
+            let Some(guard) = active[idx] else {
+                continue;
+            };
+            let Some(next) = guard.ahead(board) else {
+                active[idx] = None;
+                fates[idx] = Some(Fate::Exited(step));
+                continue;
+            };
+            let blocked = board[next.0][next.1] == Square::Full
+                || (interaction == GuardInteraction::Collide
+                    && active.iter().flatten().any(|other| other.pos == next));
+            if blocked {
+                active[idx] = Some(Guard {
+                    direction: guard.direction.turn(turn),
+                    ..guard
+                });
             } else {
-                (guard_i, guard_j) = (next_guard_i, next_guard_j);
+                active[idx] = Some(Guard { pos: next, ..guard });
+                paths[idx].push(next);
             }
+        }
+    }
+
+    guards
+        .iter()
+        .zip(paths)
+        .zip(fates)
+        .map(|((&start, path), fate)| GuardReport {
+            start,
+            path,
+            fate: fate.unwrap(),
+        })
+        .collect()
+}
+
+fn print_guard_reports(reports: &[GuardReport], show_paths: bool) {
+    for (idx, report) in reports.iter().enumerate() {
+        let distinct = report.path.iter().collect::<HashSet<_>>().len();
+        let fate = match report.fate {
+            Fate::Exited(step) => format!("left the board on step {}", step),
+            Fate::Looped(cycle) => format!(
+                "loops from step {} with period {}",
+                cycle.start, cycle.period
+            ),
+        };
+        println!(
+            "guard {} starting at {:?} facing {:?}: visited {} squares, {}",
+            idx + 1,
+            report.start.pos,
+            report.start.direction,
+            distinct,
+            fate
+        );
+        if show_paths {
+            println!("  path: {:?}", report.path);
+        }
+    }
+}
+
+// Walk the guard square by square, returning every square visited (in the order first reached),
+// and whether it was a loop rather than walking off the board.
+fn route(
+    row_size: usize,
+    board: &[Vec<Square>],
+    guard_start: Guard,
+) -> (Vec<(usize, usize)>, bool) {
+    // One bit per direction the guard has been facing on each square.
+    let mut seen_facing = vec![0_u8; board.len() * row_size];
+    let mut visited = Vec::new();
+    let mut guard = guard_start;
+    loop {
+        let cell = &mut seen_facing[guard.pos.0 * row_size + guard.pos.1];
+        if *cell & (1 << (guard.direction as usize)) != 0 {
+            return (visited, true);
+        }
+        if *cell == 0 {
+            visited.push(guard.pos);
+        }
+        *cell |= 1 << (guard.direction as usize);
+        let Some(next) = guard.ahead(board) else {
+            return (visited, false);
+        };
+        if board[next.0][next.1] == Square::Full {
+            guard.direction = guard.direction.turn(TurnRule::Right);
         } else {
-            // ... or if we go negative
-            break;
+            guard.pos = next;
+        }
+    }
+}
+
+// Marks a guard walking off the board in a JumpTable.
+const EXITS: u32 = u32::MAX;
+
+// For every square and direction, where a guard walking that way stops: the last square before
+// an obstacle, or EXITS if it walks off the board. With it the guard moves a whole straight
+// segment at a time, rather than square by square.
+struct JumpTable {
+    row_size: usize,
+    stops: Vec<u32>,
+}
+
+impl JumpTable {
+    fn new(row_size: usize, board: &[Vec<Square>]) -> Self {
+        let cell = |i: usize, j: usize| (i * row_size + j) as u32;
+        let mut table = Self {
+            row_size,
+            stops: vec![EXITS; board.len() * row_size * 4],
+        };
+        // Each pass walks towards the obstacles it's looking for, so `obstacle` is the nearest one
+        // in the direction of travel.
+        for (i, row) in board.iter().enumerate() {
+            let mut obstacle = None;
+            for (j, &square) in row.iter().enumerate() {
+                if square == Square::Full {
+                    obstacle = Some(j);
+                } else {
+                    let stop = obstacle.map_or(EXITS, |o| cell(i, o + 1));
+                    table.set((i, j), Direction::Left, stop);
+                }
+            }
+            let mut obstacle = None;
+            for (j, &square) in row.iter().enumerate().rev() {
+                if square == Square::Full {
+                    obstacle = Some(j);
+                } else {
+                    let stop = obstacle.map_or(EXITS, |o| cell(i, o - 1));
+                    table.set((i, j), Direction::Right, stop);
+                }
+            }
+        }
+        for j in 0..row_size {
+            let mut obstacle = None;
+            for (i, row) in board.iter().enumerate() {
+                if row[j] == Square::Full {
+                    obstacle = Some(i);
+                } else {
+                    let stop = obstacle.map_or(EXITS, |o| cell(o + 1, j));
+                    table.set((i, j), Direction::Up, stop);
+                }
+            }
+            let mut obstacle = None;
+            for (i, row) in board.iter().enumerate().rev() {
+                if row[j] == Square::Full {
+                    obstacle = Some(i);
+                } else {
+                    let stop = obstacle.map_or(EXITS, |o| cell(o - 1, j));
+                    table.set((i, j), Direction::Down, stop);
+                }
+            }
         }
+        table
     }
 
-    true
+    fn set(&mut self, pos: (usize, usize), direction: Direction, stop: u32) {
+        self.stops[(pos.0 * self.row_size + pos.1) * 4 + direction as usize] = stop;
+    }
+
+    // Where a guard at `pos` facing `direction` stops, given one extra `obstruction` on the
+    // board, or None if it walks off. Rather than rewriting the table for each obstruction, a
+    // jump that would pass over it is cut short.
+    fn stop(
+        &self,
+        pos: (usize, usize),
+        direction: Direction,
+        obstruction: (usize, usize),
+    ) -> Option<(usize, usize)> {
+        let stop = self.stops[(pos.0 * self.row_size + pos.1) * 4 + direction as usize];
+        let table_stop = (stop != EXITS).then(|| {
+            let stop = stop as usize;
+            (stop / self.row_size, stop % self.row_size)
+        });
+        let (o_i, o_j) = obstruction;
+        let cut_short = match direction {
+            Direction::Up => {
+                o_j == pos.1 && o_i < pos.0 && table_stop.is_none_or(|(i, _)| o_i >= i)
+            }
+            Direction::Down => {
+                o_j == pos.1 && o_i > pos.0 && table_stop.is_none_or(|(i, _)| o_i <= i)
+            }
+            Direction::Left => {
+                o_i == pos.0 && o_j < pos.1 && table_stop.is_none_or(|(_, j)| o_j >= j)
+            }
+            Direction::Right => {
+                o_i == pos.0 && o_j > pos.1 && table_stop.is_none_or(|(_, j)| o_j <= j)
+            }
+        };
+        if !cut_short {
+            return table_stop;
+        }
+        let (delta_i, delta_j) = direction.to_tuple();
+        Some((
+            (o_i as i64 - delta_i as i64) as usize,
+            (o_j as i64 - delta_j as i64) as usize,
+        ))
+    }
+
+    // Whether the guard loops once `obstruction` is added. `seen` records the states reached at
+    // the end of each segment; entries equal to `stamp` are from this run, so it never needs
+    // clearing between runs.
+    fn loops_with(
+        &self,
+        start: Guard,
+        obstruction: (usize, usize),
+        seen: &mut [u32],
+        stamp: u32,
+    ) -> bool {
+        let mut guard = start;
+        loop {
+            let Some(stop) = self.stop(guard.pos, guard.direction, obstruction) else {
+                return false;
+            };
+            guard = Guard {
+                pos: stop,
+                direction: guard.direction.turn(TurnRule::Right),
+            };
+            let key = (stop.0 * self.row_size + stop.1) * 4 + guard.direction as usize;
+            if seen[key] == stamp {
+                return true;
+            }
+            seen[key] = stamp;
+        }
+    }
 }
 
 // Simulate, returning both the # positions visited in a successful run and the number of ways
@@ -90,35 +383,141 @@ fn simulate(
 fn simulate_and_count_positions(
     row_size: usize,
     board: &[Vec<Square>],
-    guard_start: (usize, usize),
+    guard_start: Guard,
 ) -> (usize, usize) {
-    let mut loop_checker: Vec<u8> = vec![0; board.len() * row_size];
+    let (visited, _) = route(row_size, board, guard_start);
 
-    simulate(row_size, board, guard_start, &mut loop_checker);
+    let table = JumpTable::new(row_size, board);
+    let mut seen = vec![0; board.len() * row_size * 4];
+    // The puzzle doesn't allow an obstruction on the guard's starting square.
+    let loop_count = visited
+        .iter()
+        .enumerate()
+        .filter(|&(_, &pos)| pos != guard_start.pos)
+        .filter(|&(stamp, &pos)| table.loops_with(guard_start, pos, &mut seen, stamp as u32 + 1))
+        .count();
 
-    let mut visited = Vec::new();
-    for (i, &s) in loop_checker.iter().enumerate() {
-        if s != 0 {
-            visited.push((i / row_size, i % row_size));
+    (visited.len(), loop_count)
+}
+
+#[derive(Debug, Clone)]
+struct Loop {
+    // The guard's position and heading when it first enters the loop.
+    entry: Guard,
+    cycle: Cycle,
+    // The guard's states once around the loop, starting from `entry`.
+    states: Vec<Guard>,
+}
+
+// Walk a single guard (turning right) until it leaves the board, returning its loop if it never
+// does.
+fn find_loop(board: &[Vec<Square>], start: Guard) -> Option<Loop> {
+    let mut seen = HashMap::new();
+    let mut states = Vec::new();
+    let mut guard = start;
+    loop {
+        if let Some(&loop_start) = seen.get(&guard) {
+            return Some(Loop {
+                entry: guard,
+                cycle: Cycle {
+                    start: loop_start,
+                    period: states.len() - loop_start,
+                },
+                states: states.split_off(loop_start),
+            });
+        }
+        seen.insert(guard, states.len());
+        states.push(guard);
+        let next = guard.ahead(board)?;
+        if board[next.0][next.1] == Square::Full {
+            guard.direction = guard.direction.turn(TurnRule::Right);
+        } else {
+            guard.pos = next;
         }
     }
+}
+
+// Every square where a new obstruction would trap the guard in a loop, along with that loop.
+// Candidates are the squares on the guard's unobstructed route, other than its starting square
+// (where the puzzle doesn't allow an obstruction).
+fn loop_obstructions(board: &[Vec<Square>], start: Guard) -> Vec<((usize, usize), Loop)> {
+    let route = simulate_guards(board, &[start], TurnRule::Right, GuardInteraction::Ignore);
+    let mut candidates = route[0].path.clone();
+    candidates.sort();
+    candidates.dedup();
 
     let mut modified_board = board.to_vec();
-    let mut loop_count = 0;
-    for &(i, j) in visited.iter() {
+    let mut out = Vec::new();
+    for (i, j) in candidates {
+        if (i, j) == start.pos {
+            continue;
+        }
         modified_board[i][j] = Square::Full;
-        if !simulate(row_size, &modified_board, guard_start, &mut loop_checker) {
-            loop_count += 1;
+        if let Some(found) = find_loop(&modified_board, start) {
+            out.push(((i, j), found));
         }
         modified_board[i][j] = Square::Empty;
     }
+    out
+}
 
-    (visited.len(), loop_count)
+fn print_loop_obstructions(obstructions: &[((usize, usize), Loop)]) {
+    for (pos, found) in obstructions.iter() {
+        println!(
+            "obstruction at {:?}: loop entered at {:?} facing {:?} on step {}, period {}",
+            pos, found.entry.pos, found.entry.direction, found.cycle.start, found.cycle.period
+        );
+    }
+    println!("{} obstructions cause loops", obstructions.len());
+}
+
+// Draw the map with the obstruction as 'O' and the loop drawn the way the puzzle does: '|' and
+// '-' for straight runs, '+' where the guard turns or crosses its own path.
+fn print_loop_overlay(
+    board: &[Vec<Square>],
+    start: Guard,
+    obstruction: (usize, usize),
+    found: &Loop,
+) {
+    let mut vertical = HashSet::new();
+    let mut horizontal = HashSet::new();
+    for state in found.states.iter() {
+        match state.direction {
+            Direction::Up | Direction::Down => vertical.insert(state.pos),
+            Direction::Left | Direction::Right => horizontal.insert(state.pos),
+        };
+    }
+    for (i, row) in board.iter().enumerate() {
+        for (j, &square) in row.iter().enumerate() {
+            let pos = (i, j);
+            let c = if pos == obstruction {
+                'O'
+            } else if square == Square::Full {
+                '#'
+            } else if pos == start.pos {
+                match start.direction {
+                    Direction::Up => '^',
+                    Direction::Right => '>',
+                    Direction::Down => 'v',
+                    Direction::Left => '<',
+                }
+            } else {
+                match (vertical.contains(&pos), horizontal.contains(&pos)) {
+                    (true, true) => '+',
+                    (true, false) => '|',
+                    (false, true) => '-',
+                    (false, false) => '.',
+                }
+            };
+            print!("{}", c);
+        }
+        println!();
+    }
 }
 
 fn main() {
     let mut board = Vec::new();
-    let mut guard_pos = (None, None);
+    let mut guards = Vec::new();
     let mut row_size = 0;
     for (i, line) in util::get_lines().map_while(Result::ok).enumerate() {
         if i == 0 {
@@ -129,19 +528,86 @@ fn main() {
             let square = match c {
                 '#' => Square::Full,
                 '.' => Square::Empty,
-                '^' => {
-                    guard_pos = (Some(i), Some(j));
-                    Square::Empty
-                }
-                _ => panic!("invalid char {}", c),
+                _ => match Direction::from_marker(c) {
+                    Some(direction) => {
+                        guards.push(Guard {
+                            pos: (i, j),
+                            direction,
+                        });
+                        Square::Empty
+                    }
+                    None => panic!("invalid char {}", c),
+                },
             };
             row.push(square);
         }
         board.push(row);
     }
-    let guard_pos = (guard_pos.0.unwrap(), guard_pos.1.unwrap());
+    if util::has_flag("--guards") {
+        let turn = match util::get_flag_value("--turn").as_deref() {
+            None | Some("right") => TurnRule::Right,
+            Some("left") => TurnRule::Left,
+            Some("reverse") => TurnRule::Reverse,
+            Some(other) => panic!("unknown turn rule {}", other),
+        };
+        let interaction = if util::has_flag("--ignore-guards") {
+            GuardInteraction::Ignore
+        } else {
+            GuardInteraction::Collide
+        };
+        let reports = simulate_guards(&board, &guards, turn, interaction);
+        print_guard_reports(&reports, util::has_flag("--paths"));
+    }
+    if util::has_flag("--loops") || util::get_flag_value("--show-loop").is_some() {
+        let obstructions = loop_obstructions(&board, guards[0]);
+        if util::has_flag("--loops") {
+            print_loop_obstructions(&obstructions);
+        }
+        if let Some(arg) = util::get_flag_value("--show-loop") {
+            let (i, j) = arg.split_once(',').expect("--show-loop takes i,j");
+            let pos = (i.parse().unwrap(), j.parse().unwrap());
+            match obstructions.iter().find(|(p, _)| *p == pos) {
+                Some((_, found)) => print_loop_overlay(&board, guards[0], pos, found),
+                None => println!("an obstruction at {:?} doesn't cause a loop", pos),
+            }
+        }
+    }
     println!(
         "visited, ways to loop {:?}",
-        simulate_and_count_positions(row_size, &board, guard_pos)
+        simulate_and_count_positions(row_size, &board, guards[0])
     );
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn no_obstruction_on_start() {
+        // Blocking the start square would trap the guard, but isn't allowed.
+        let rows = [
+            "#.....##", "##.#....", "...#.#..", "........", ".....#..", "...#....", "^..#....",
+            "..#...#.",
+        ];
+        let board = rows
+            .iter()
+            .map(|row| {
+                row.chars()
+                    .map(|c| {
+                        if c == '#' {
+                            Square::Full
+                        } else {
+                            Square::Empty
+                        }
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        let start = Guard {
+            pos: (6, 0),
+            direction: Direction::Up,
+        };
+        assert_eq!(simulate_and_count_positions(8, &board, start), (12, 0));
+        assert!(loop_obstructions(&board, start).is_empty());
+    }
+}
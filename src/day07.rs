@@ -1,77 +1,505 @@
+use anyhow::{anyhow, bail, Result};
 use aoc_2024::util;
+use num_bigint::BigInt;
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
 
-fn is_satisfiable_add_mul(equation: &[i64]) -> bool {
-    let (target, rest) = (equation[0], &equation[1..]);
+// Arithmetic the solvers need, so they can run on i64 or, for targets too large for that,
+// BigInt. The checked operations return None when the result doesn't fit.
+trait Number: Clone + Ord {
+    fn zero() -> Self;
+    fn checked_add(&self, rhs: &Self) -> Option<Self>;
+    fn checked_sub(&self, rhs: &Self) -> Option<Self>;
+    fn checked_mul(&self, rhs: &Self) -> Option<Self>;
+    // The quotient, if `rhs` is non-zero and divides self exactly. Fails if the quotient doesn't
+    // fit.
+    fn exact_div(&self, rhs: &Self) -> Result<Option<Self>, Overflow>;
+    // For non-negative self, 10 to the power of its number of digits, so that concatenating x
+    // and self gives x * shift + self.
+    fn concat_shift(&self) -> Option<Self>;
+}
+
+impl Number for i64 {
+    fn zero() -> Self {
+        0
+    }
+    fn checked_add(&self, rhs: &Self) -> Option<Self> {
+        i64::checked_add(*self, *rhs)
+    }
+    fn checked_sub(&self, rhs: &Self) -> Option<Self> {
+        i64::checked_sub(*self, *rhs)
+    }
+    fn checked_mul(&self, rhs: &Self) -> Option<Self> {
+        i64::checked_mul(*self, *rhs)
+    }
+    fn exact_div(&self, rhs: &Self) -> Result<Option<Self>, Overflow> {
+        if *rhs == 0 {
+            return Ok(None);
+        }
+        // Only i64::MIN / -1 overflows.
+        let quotient = self.checked_div(*rhs).ok_or(Overflow)?;
+        Ok((self % rhs == 0).then_some(quotient))
+    }
+    fn concat_shift(&self) -> Option<Self> {
+        // ilog10 panics on zero, which still has one digit.
+        let digits = self.checked_ilog10().unwrap_or(0) + 1;
+        10_i64.checked_pow(digits)
+    }
+}
+
+impl Number for BigInt {
+    fn zero() -> Self {
+        BigInt::ZERO
+    }
+    fn checked_add(&self, rhs: &Self) -> Option<Self> {
+        Some(self + rhs)
+    }
+    fn checked_sub(&self, rhs: &Self) -> Option<Self> {
+        Some(self - rhs)
+    }
+    fn checked_mul(&self, rhs: &Self) -> Option<Self> {
+        Some(self * rhs)
+    }
+    fn exact_div(&self, rhs: &Self) -> Result<Option<Self>, Overflow> {
+        Ok((*rhs != BigInt::ZERO && (self % rhs) == BigInt::ZERO).then(|| self / rhs))
+    }
+    fn concat_shift(&self) -> Option<Self> {
+        let digits = self.magnitude().to_string().len();
+        Some(BigInt::from(10).pow(digits as u32))
+    }
+}
+
+// An intermediate value didn't fit in the number type being used.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct Overflow;
+
+// The x such that x concatenated with `suffix` is `whole`, if there is one.
+fn strip_concat<N: Number>(whole: &N, suffix: &N) -> Option<N> {
+    if *suffix < N::zero() || whole < suffix {
+        return None;
+    }
+    let prefix_part = whole.checked_sub(suffix)?;
+    match suffix.concat_shift() {
+        // The shift is positive, so the quotient is no bigger than `whole`.
+        Some(shift) => prefix_part.exact_div(&shift).ok()?,
+        // The shift is bigger than any value of N, so only a prefix of zero fits.
+        None => (prefix_part == N::zero()).then(N::zero),
+    }
+}
+
+fn is_satisfiable_add_mul<N: Number>(equation: &[N]) -> Result<bool, Overflow> {
+    let (target, rest) = (&equation[0], &equation[1..]);
+    // Adding or multiplying by positive operands never makes a positive total smaller, so once
+    // it passes the target there's no point going on, unless a zero (or negative) operand is
+    // still to come.
+    let positive_from = rest
+        .iter()
+        .rposition(|val| *val <= N::zero())
+        .map_or(0, |i| i + 1);
     for bits in 0..2_usize.pow((rest.len() - 1) as u32) {
-        let mut tmp = rest[0];
+        let mut tmp = rest[0].clone();
         for (i, val) in rest.iter().skip(1).enumerate() {
-            if tmp > target {
+            if tmp > *target && tmp > N::zero() && i + 1 >= positive_from {
                 break;
             }
-            if bits & (1 << i) != 0 {
-                tmp *= val;
+            tmp = if bits & (1 << i) != 0 {
+                tmp.checked_mul(val)
             } else {
-                tmp += val;
+                tmp.checked_add(val)
             }
+            .ok_or(Overflow)?;
         }
-        if tmp == target {
-            return true;
+        if tmp == *target {
+            return Ok(true);
         }
     }
-    false
+    Ok(false)
 }
 
-fn is_satisfiable_add_mul_concat(equation: &[i64]) -> bool {
-    fn helper(target: i64, rest: &mut [i64]) -> bool {
-        if let Some(&next) = rest.last() {
-            let new_len = rest.len() - 1;
-            if target >= next && helper(target - next, &mut rest[..new_len]) {
-                return true;
+fn is_satisfiable_add_mul_concat<N: Number>(equation: &[N]) -> Result<bool, Overflow> {
+    // Works backwards from the target, undoing the last operator each time.
+    fn helper<N: Number>(target: &N, rest: &[N]) -> Result<bool, Overflow> {
+        let (next, rest) = rest.split_last().unwrap();
+        if rest.is_empty() {
+            return Ok(target == next);
+        }
+        if target >= next && helper(&target.checked_sub(next).ok_or(Overflow)?, rest)? {
+            return Ok(true);
+        }
+        if *next == N::zero() {
+            // Anything times zero is zero.
+            if *target == N::zero() {
+                return Ok(true);
             }
-            if target % next == 0 && helper(target / next, &mut rest[..new_len]) {
-                return true;
+        } else if let Some(quotient) = target.exact_div(next)? {
+            if helper(&quotient, rest)? {
+                return Ok(true);
             }
-            let multiplier = 10_i64.pow(next.ilog10() + 1);
-            if target % multiplier == next && helper(target / multiplier, &mut rest[..new_len]) {
-                return true;
+        }
+        if let Some(prefix) = strip_concat(target, next) {
+            if helper(&prefix, rest)? {
+                return Ok(true);
             }
-            false
-        } else {
-            // Empty
-            target == 0
         }
+        Ok(false)
+    }
+    helper(&equation[0], &equation[1..])
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+enum Operator {
+    Add,
+    Mul,
+    // Concatenate the digits of both sides, written in the given base.
+    Concat(u32),
+    Sub,
+    // Only applies when the division is exact.
+    Div,
+    Xor,
+}
+
+impl Operator {
+    // Apply to the running total and the next operand, or None if this operator can't be used
+    // here (an inexact division, or the result doesn't fit).
+    fn apply(self, lhs: i64, rhs: i64) -> Option<i64> {
+        match self {
+            Operator::Add => lhs.checked_add(rhs),
+            Operator::Mul => lhs.checked_mul(rhs),
+            Operator::Concat(base) => {
+                if rhs < 0 {
+                    return None;
+                }
+                let base = i64::from(base);
+                let mut shift = base;
+                while shift <= rhs {
+                    shift = shift.checked_mul(base)?;
+                }
+                lhs.checked_mul(shift)?.checked_add(rhs)
+            }
+            Operator::Sub => lhs.checked_sub(rhs),
+            Operator::Div => {
+                // Both are None when rhs is zero, or for i64::MIN / -1, which doesn't fit.
+                let quotient = lhs.checked_div(rhs)?;
+                (lhs.checked_rem(rhs)? == 0).then_some(quotient)
+            }
+            Operator::Xor => Some(lhs ^ rhs),
+        }
+    }
+
+    // Whether applying this to a non-negative total and a positive operand never makes the total
+    // smaller, so a search can give up once the total passes the target.
+    fn never_decreases(self) -> bool {
+        matches!(self, Operator::Add | Operator::Mul | Operator::Concat(_))
+    }
+}
+
+impl FromStr for Operator {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "add" | "+" => Operator::Add,
+            "mul" | "*" => Operator::Mul,
+            "concat" | "||" => Operator::Concat(10),
+            "sub" | "-" => Operator::Sub,
+            "div" | "/" => Operator::Div,
+            "xor" | "^" => Operator::Xor,
+            _ => {
+                // "concat<base>", e.g. concat2 or concat16.
+                let base = s
+                    .strip_prefix("concat")
+                    .and_then(|base| base.parse::<u32>().ok())
+                    .filter(|base| *base >= 2)
+                    .ok_or_else(|| anyhow!("unknown operator {}", s))?;
+                Operator::Concat(base)
+            }
+        })
+    }
+}
+
+impl fmt::Display for Operator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Operator::Add => write!(f, "+"),
+            Operator::Mul => write!(f, "*"),
+            Operator::Concat(10) => write!(f, "||"),
+            Operator::Concat(base) => write!(f, "||[base {}]", base),
+            Operator::Sub => write!(f, "-"),
+            Operator::Div => write!(f, "/"),
+            Operator::Xor => write!(f, "^"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Solutions {
+    // How many assignments of operators (evaluated left to right) reach the target. This grows
+    // exponentially with the number of operands, so can be too big for any fixed-size integer.
+    count: BigInt,
+    // Up to the requested number of those assignments.
+    witnesses: Vec<Vec<Operator>>,
+}
+
+// Depth-first search for assignments of operators reaching `target`, stopping after `limit`.
+struct WitnessSearch<'a> {
+    target: i64,
+    operators: &'a [Operator],
+    prune: bool,
+    limit: usize,
+    chosen: Vec<Operator>,
+    found: Vec<Vec<Operator>>,
+}
+
+impl WitnessSearch<'_> {
+    fn search(&mut self, total: i64, rest: &[i64]) {
+        if self.found.len() >= self.limit || (self.prune && total > self.target) {
+            return;
+        }
+        let Some((&val, rest)) = rest.split_first() else {
+            if total == self.target {
+                self.found.push(self.chosen.clone());
+            }
+            return;
+        };
+        for &op in self.operators.iter() {
+            if let Some(new_total) = op.apply(total, val) {
+                self.chosen.push(op);
+                self.search(new_total, rest);
+                self.chosen.pop();
+            }
+        }
+    }
+}
+
+// Find the ways to place `operators` between the operands of `equation` so that, evaluated left
+// to right, they produce its target.
+fn solve(equation: &[i64], operators: &[Operator], witness_limit: usize) -> Solutions {
+    let (target, rest) = (equation[0], &equation[1..]);
+    let prune = operators.iter().all(|op| op.never_decreases()) && rest.iter().all(|&v| v > 0);
+
+    // Count by tracking how many assignments reach each running total, so that assignments
+    // which agree on a prefix's value are only extended once.
+    let mut totals = HashMap::from([(rest[0], BigInt::from(1))]);
+    for &val in rest.iter().skip(1) {
+        let mut next = HashMap::new();
+        for (&total, ways) in totals.iter() {
+            for op in operators.iter() {
+                if let Some(new_total) = op.apply(total, val) {
+                    if !(prune && new_total > target) {
+                        *next.entry(new_total).or_insert(BigInt::ZERO) += ways;
+                    }
+                }
+            }
+        }
+        totals = next;
+    }
+    let count = totals.remove(&target).unwrap_or(BigInt::ZERO);
+
+    let mut search = WitnessSearch {
+        target,
+        operators,
+        prune,
+        limit: witness_limit,
+        chosen: Vec::new(),
+        found: Vec::new(),
+    };
+    if count > BigInt::ZERO {
+        search.search(rest[0], &rest[1..]);
+    }
+    let witnesses = search.found;
+    Solutions { count, witnesses }
+}
+
+fn format_expression(operands: &[i64], operators: &[Operator]) -> String {
+    let mut out = operands[0].to_string();
+    for (op, val) in operators.iter().zip(operands.iter().skip(1)) {
+        out += &format!(" {} {}", op, val);
+    }
+    out
+}
+
+// Solve every equation with the given operators, printing each satisfiable one with its
+// witnesses and count, and return the sum of their targets.
+fn print_solutions(equations: &[Vec<i64>], operators: &[Operator], witness_limit: usize) -> i64 {
+    let mut sum = 0;
+    for equation in equations.iter() {
+        let solutions = solve(equation, operators, witness_limit);
+        if solutions.count == BigInt::ZERO {
+            continue;
+        }
+        sum += equation[0];
+        println!("{}: {} ways", equation[0], solutions.count);
+        for witness in solutions.witnesses.iter() {
+            println!(
+                "  {} = {}",
+                equation[0],
+                format_expression(&equation[1..], witness)
+            );
+        }
+    }
+    sum
+}
+
+// How to do the arithmetic for parts 1 and 2.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Backend {
+    // i64, redoing an equation with BigInt if it doesn't fit.
+    Auto,
+    // i64 only, failing if anything doesn't fit.
+    Checked,
+    // BigInt for everything.
+    Big,
+}
+
+struct Equation {
+    // The target followed by the operands.
+    big: Vec<BigInt>,
+    // The same, if every value fits in an i64.
+    small: Option<Vec<i64>>,
+}
+
+impl FromStr for Equation {
+    type Err = anyhow::Error;
+    fn from_str(line: &str) -> Result<Self> {
+        let (lhs, rhs) = line
+            .split_once(':')
+            .ok_or_else(|| anyhow!("missing ':' in {:?}", line))?;
+        let big = std::iter::once(lhs)
+            .chain(rhs.split_ascii_whitespace())
+            .map(|s| s.trim().parse::<BigInt>())
+            .collect::<Result<Vec<_>, _>>()?;
+        if big.len() < 2 {
+            bail!("no operands in {:?}", line);
+        }
+        let small = big
+            .iter()
+            .map(|n| i64::try_from(n).ok())
+            .collect::<Option<Vec<_>>>();
+        Ok(Equation { big, small })
     }
-    let target = equation[0];
-    let mut rest = equation[1..].to_vec();
-    helper(target, &mut rest)
 }
 
-fn sum_satisfiable_equations(equations: &[Vec<i64>], f: fn(&[i64]) -> bool) -> i64 {
-    equations.iter().filter(|e| f(e)).map(|e| e[0]).sum()
+fn sum_satisfiable_equations(
+    equations: &[Equation],
+    backend: Backend,
+    small: fn(&[i64]) -> Result<bool, Overflow>,
+    big: fn(&[BigInt]) -> Result<bool, Overflow>,
+) -> Result<BigInt> {
+    let mut sum = BigInt::ZERO;
+    for equation in equations.iter() {
+        let target = &equation.big[0];
+        let attempt = match (backend, &equation.small) {
+            (Backend::Big, _) | (Backend::Auto, None) => None,
+            (Backend::Checked, None) => bail!("{} doesn't fit in 64 bits; try --big", target),
+            (_, Some(values)) => Some(small(values)),
+        };
+        let satisfiable = match attempt {
+            Some(Ok(satisfiable)) => satisfiable,
+            Some(Err(Overflow)) if backend == Backend::Checked => {
+                bail!("overflow while checking {}; try --big", target)
+            }
+            // BigInt arithmetic never overflows.
+            _ => big(&equation.big).unwrap(),
+        };
+        if satisfiable {
+            sum += target;
+        }
+    }
+    Ok(sum)
 }
 
-fn main() {
+fn main() -> Result<()> {
     let mut equations = Vec::new();
     for line in util::get_lines().map_while(Result::ok) {
-        let mut sides = line.split(":");
-        let lhs = sides.next().unwrap().parse::<i64>().unwrap();
-        let rhs = sides
-            .next()
-            .unwrap()
-            .trim()
-            .split_ascii_whitespace()
-            .map(|s| s.parse::<i64>().unwrap());
-        let mut equation = Vec::new();
-        equation.push(lhs);
-        equation.extend_from_slice(&rhs.collect::<Vec<_>>());
-        equations.push(equation);
+        equations.push(line.parse::<Equation>()?);
+    }
+    let backend = if util::has_flag("--big") {
+        Backend::Big
+    } else if util::has_flag("--checked") {
+        Backend::Checked
+    } else {
+        Backend::Auto
+    };
+    if let Some(ops) = util::get_flag_value("--ops") {
+        let operators = ops
+            .split(',')
+            .map(|op| op.parse::<Operator>())
+            .collect::<Result<Vec<_>>>()?;
+        let small = equations
+            .iter()
+            .map(|e| e.small.clone())
+            .collect::<Option<Vec<_>>>()
+            .ok_or_else(|| anyhow!("--ops only supports values that fit in 64 bits"))?;
+        let witness_limit = if util::has_flag("--all") {
+            usize::MAX
+        } else {
+            1
+        };
+        let sum = print_solutions(&small, &operators, witness_limit);
+        println!("sum with {}: {}", ops, sum);
     }
     println!(
         "sum: {}",
-        sum_satisfiable_equations(&equations, is_satisfiable_add_mul)
+        sum_satisfiable_equations(
+            &equations,
+            backend,
+            is_satisfiable_add_mul,
+            is_satisfiable_add_mul
+        )?
     );
     println!(
         "sum: {}",
-        sum_satisfiable_equations(&equations, is_satisfiable_add_mul_concat)
+        sum_satisfiable_equations(
+            &equations,
+            backend,
+            is_satisfiable_add_mul_concat,
+            is_satisfiable_add_mul_concat
+        )?
     );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn zero_operands() {
+        // 5 * 0 + 3: the total passes the target before the zero brings it back down.
+        let equation = [3_i64, 5, 0, 3];
+        assert_eq!(is_satisfiable_add_mul(&equation), Ok(true));
+        assert_eq!(
+            is_satisfiable_add_mul(&equation.map(BigInt::from)),
+            Ok(true)
+        );
+        assert_eq!(is_satisfiable_add_mul_concat(&equation), Ok(true));
+        assert_eq!(is_satisfiable_add_mul(&[0_i64, 5, 0]), Ok(true));
+        assert_eq!(is_satisfiable_add_mul(&[4_i64, 5, 0, 3]), Ok(false));
+
+        // Agrees with the general solver on the same operators.
+        let operators = [Operator::Add, Operator::Mul];
+        for equation in [
+            [0_i64, 5, 0, 0],
+            [3, 5, 0, 3],
+            [4, 5, 0, 3],
+            [190, 10, 19, 0],
+        ] {
+            assert_eq!(
+                is_satisfiable_add_mul(&equation),
+                Ok(solve(&equation, &operators, 0).count > BigInt::ZERO),
+                "{:?}",
+                equation
+            );
+        }
+    }
+
+    #[test]
+    fn division_overflow() {
+        assert_eq!(Operator::Div.apply(i64::MIN, -1), None);
+        assert_eq!(Operator::Div.apply(12, -3), Some(-4));
+        assert_eq!(
+            is_satisfiable_add_mul_concat(&[i64::MIN, i64::MIN, -1]),
+            Err(Overflow)
+        );
+    }
 }
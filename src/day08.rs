@@ -1,5 +1,7 @@
+use anyhow::{anyhow, bail, Result};
 use aoc_2024::util;
 use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 enum Square {
@@ -7,70 +9,292 @@ enum Square {
     Full(char),
 }
 
-fn find_and_count_antinodes(map: &[Vec<Square>], repeated: bool) -> usize {
-    let mut antinodes: HashSet<(usize, usize)> = HashSet::new();
-    let mut frequency_positions = HashMap::new();
-    for (i, row) in map.iter().enumerate() {
-        for (j, &square) in row.iter().enumerate() {
-            if let Square::Full(c) = square {
-                frequency_positions
-                    .entry(c)
-                    .or_insert_with(Vec::new)
-                    .push((i, j));
+// (layer, row, column). A 2D map is a single layer.
+type Point = [i64; 3];
+
+fn offset(p: Point, step: Point, times: i64) -> Point {
+    [
+        p[0] + step[0] * times,
+        p[1] + step[1] * times,
+        p[2] + step[2] * times,
+    ]
+}
+
+fn gcd(a: i64, b: i64) -> i64 {
+    if b == 0 {
+        a.abs()
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+struct Map {
+    layers: Vec<Vec<Vec<Square>>>,
+    // Number of layers, rows and columns.
+    size: Point,
+}
+
+impl Map {
+    // Layers are separated by blank lines.
+    fn parse(lines: impl Iterator<Item = String>) -> Self {
+        let mut layers = vec![Vec::new()];
+        for line in lines {
+            if line.is_empty() {
+                layers.push(Vec::new());
+                continue;
             }
+            let row = line
+                .chars()
+                .map(|c| {
+                    if c == '.' {
+                        Square::Empty
+                    } else {
+                        Square::Full(c)
+                    }
+                })
+                .collect::<Vec<_>>();
+            layers.last_mut().unwrap().push(row);
         }
+        layers.retain(|layer| !layer.is_empty());
+        let size = [
+            layers.len() as i64,
+            layers.first().map_or(0, |layer| layer.len() as i64),
+            layers
+                .first()
+                .and_then(|layer| layer.first())
+                .map_or(0, |row| row.len() as i64),
+        ];
+        Map { layers, size }
     }
 
-    for positions in frequency_positions.values() {
-        for (i, p1) in positions.iter().enumerate() {
-            for p2 in positions.iter().skip(i + 1) {
-                let x_diff = p2.0 as i64 - p1.0 as i64;
-                let y_diff = p2.1 as i64 - p1.1 as i64;
-                if repeated {
-                    antinodes.insert(*p1);
-                    antinodes.insert(*p2);
-                }
-                // check (p2.0 + x_diff, p2.1 + y_diff) and (p1.0 - x_diff, p1.1 - y_diff)
-                let mut near_p2 = (p2.0 as i64 + x_diff, p2.1 as i64 + y_diff);
-                while (0..map.len() as i64).contains(&near_p2.0)
-                    && (0..map[0].len() as i64).contains(&near_p2.1)
-                {
-                    antinodes.insert((near_p2.0 as usize, near_p2.1 as usize));
-                    if !repeated {
-                        break;
+    fn contains(&self, p: Point) -> bool {
+        p.iter()
+            .zip(self.size.iter())
+            .all(|(x, size)| (0..*size).contains(x))
+    }
+
+    // The points `start`, `start + step`, `start + 2 * step`, ... up to the edge of the map.
+    fn walk(&self, start: Point, step: Point) -> impl Iterator<Item = Point> + '_ {
+        (0..)
+            .map(move |times| offset(start, step, times))
+            .take_while(|&p| self.contains(p))
+    }
+
+    fn format_point(&self, p: Point) -> String {
+        if self.size[0] == 1 {
+            format!("({}, {})", p[1], p[2])
+        } else {
+            format!("({}, {}, {})", p[0], p[1], p[2])
+        }
+    }
+
+    // Redraw the map with '#' at each antinode not already showing an antenna, like the puzzle's
+    // illustrations.
+    fn draw(&self, antinodes: &HashSet<Point>) {
+        for (l, layer) in self.layers.iter().enumerate() {
+            if l > 0 {
+                println!();
+            }
+            for (i, row) in layer.iter().enumerate() {
+                let line = row
+                    .iter()
+                    .enumerate()
+                    .map(|(j, square)| match square {
+                        Square::Full(c) => *c,
+                        Square::Empty if antinodes.contains(&[l as i64, i as i64, j as i64]) => '#',
+                        Square::Empty => '.',
+                    })
+                    .collect::<String>();
+                println!("{}", line);
+            }
+        }
+        println!();
+    }
+
+    fn antennas(&self) -> HashMap<char, Vec<Point>> {
+        let mut frequency_positions = HashMap::new();
+        for (l, layer) in self.layers.iter().enumerate() {
+            for (i, row) in layer.iter().enumerate() {
+                for (j, &square) in row.iter().enumerate() {
+                    if let Square::Full(c) = square {
+                        frequency_positions
+                            .entry(c)
+                            .or_insert_with(Vec::new)
+                            .push([l as i64, i as i64, j as i64]);
                     }
-                    near_p2 = (near_p2.0 + x_diff, near_p2.1 + y_diff)
                 }
-                let mut near_p1 = (p1.0 as i64 - x_diff, p1.1 as i64 - y_diff);
-                while (0..map.len() as i64).contains(&near_p1.0)
-                    && (0..map[0].len() as i64).contains(&near_p1.1)
-                {
-                    antinodes.insert((near_p1.0 as usize, near_p1.1 as usize));
-                    if !repeated {
-                        break;
-                    }
-                    near_p1 = (near_p1.0 - x_diff, near_p1.1 - y_diff);
+            }
+        }
+        frequency_positions
+    }
+}
+
+// Which points on the line through a pair of antennas are antinodes.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum AntinodeRule {
+    // Points where one antenna is `far / near` times as far away as the other (the puzzle's
+    // rule is 2/1). Points between the two antennas only count if `between` is set.
+    Ratio { far: i64, near: i64, between: bool },
+    // Every multiple of the distance between the antennas (part 2).
+    Harmonics,
+    // Every grid point on the line, including ones between the antennas when their offset
+    // isn't in lowest terms.
+    Lattice,
+}
+
+impl AntinodeRule {
+    // Whether the point `k` lattice steps from the first antenna is an antinode, when the second
+    // antenna is `gap` steps away.
+    fn accepts(self, k: i64, gap: i64) -> bool {
+        match self {
+            AntinodeRule::Ratio { far, near, between } => {
+                let (to_first, to_second) = (k.abs(), (k - gap).abs());
+                (between || !(0..=gap).contains(&k))
+                    && (near * to_first == far * to_second || far * to_first == near * to_second)
+            }
+            AntinodeRule::Harmonics => k % gap == 0,
+            AntinodeRule::Lattice => true,
+        }
+    }
+}
+
+impl FromStr for AntinodeRule {
+    type Err = anyhow::Error;
+    // "harmonics", "lattice", or a ratio like "3/1".
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "harmonics" => AntinodeRule::Harmonics,
+            "lattice" => AntinodeRule::Lattice,
+            _ => {
+                let (far, near) = s
+                    .split_once('/')
+                    .ok_or_else(|| anyhow!("unknown antinode rule {}", s))?;
+                let (far, near) = (far.parse::<i64>()?, near.parse::<i64>()?);
+                if far <= 0 || near <= 0 {
+                    bail!("ratio {} must be of positive numbers", s);
+                }
+                AntinodeRule::Ratio {
+                    far: far.max(near),
+                    near: far.min(near),
+                    between: false,
                 }
             }
+        })
+    }
+}
+
+// Add the antinodes of the pair `a`, `b` under `rule` to `antinodes`.
+//
+// Walks the line through the pair in its smallest grid steps (the offset divided by the gcd of
+// its components) both ways from `a`, so every mode sees exactly the grid points on the line.
+fn add_pair_antinodes(
+    map: &Map,
+    rule: AntinodeRule,
+    (a, b): (Point, Point),
+    antinodes: &mut HashSet<Point>,
+) {
+    let diff = [b[0] - a[0], b[1] - a[1], b[2] - a[2]];
+    let gap = diff.iter().fold(0, |g, &x| gcd(g, x));
+    let step = diff.map(|x| x / gap);
+    for sign in [1, -1] {
+        for (k, p) in map.walk(a, step.map(|x| x * sign)).enumerate() {
+            if rule.accepts(k as i64 * sign, gap) {
+                antinodes.insert(p);
+            }
         }
     }
+}
 
-    antinodes.len()
+// The antinodes produced by each frequency's antennas under `rule`.
+fn antinodes_by_frequency(map: &Map, rule: AntinodeRule) -> HashMap<char, HashSet<Point>> {
+    let mut by_frequency = HashMap::new();
+    for (&frequency, positions) in map.antennas().iter() {
+        let mut antinodes = HashSet::new();
+        for (i, &p1) in positions.iter().enumerate() {
+            for &p2 in positions.iter().skip(i + 1) {
+                add_pair_antinodes(map, rule, (p1, p2), &mut antinodes);
+            }
+        }
+        by_frequency.insert(frequency, antinodes);
+    }
+    by_frequency
+}
+
+fn find_and_count_antinodes(map: &Map, rule: AntinodeRule) -> usize {
+    antinodes_by_frequency(map, rule)
+        .into_values()
+        .flatten()
+        .collect::<HashSet<_>>()
+        .len()
+}
+
+// Print each frequency's antinodes, then the antennas sitting on an antinode.
+fn print_breakdown(map: &Map, by_frequency: &HashMap<char, HashSet<Point>>) {
+    let mut frequencies = by_frequency.keys().copied().collect::<Vec<_>>();
+    frequencies.sort();
+    for frequency in frequencies.iter() {
+        let mut antinodes = by_frequency[frequency].iter().copied().collect::<Vec<_>>();
+        antinodes.sort();
+        println!(
+            "{}: {} antinodes: {}",
+            frequency,
+            antinodes.len(),
+            antinodes
+                .iter()
+                .map(|&p| map.format_point(p))
+                .collect::<Vec<_>>()
+                .join(" ")
+        );
+    }
+    let antennas = map.antennas();
+    for frequency in frequencies.iter() {
+        for &position in antennas[frequency].iter() {
+            let overlapping = frequencies
+                .iter()
+                .filter(|f| by_frequency[f].contains(&position))
+                .map(|f| f.to_string())
+                .collect::<Vec<_>>();
+            if !overlapping.is_empty() {
+                println!(
+                    "antenna {} at {} is an antinode of {}",
+                    frequency,
+                    map.format_point(position),
+                    overlapping.join(", ")
+                );
+            }
+        }
+    }
 }
 
-fn main() {
-    let mut map = Vec::new();
-    for line in util::get_lines().map_while(Result::ok) {
-        let mut row = Vec::new();
-        for c in line.chars() {
-            row.push(if c == '.' {
-                Square::Empty
-            } else {
-                Square::Full(c)
-            });
+fn main() -> Result<()> {
+    let map = Map::parse(util::get_lines().map_while(Result::ok));
+    let mut rules = Vec::new();
+    if let Some(rule) = util::get_flag_value("--rule") {
+        let mut rule = rule.parse::<AntinodeRule>()?;
+        if let AntinodeRule::Ratio { between, .. } = &mut rule {
+            *between = util::has_flag("--between");
+        }
+        rules.push((format!("{:?}: ", rule), rule));
+    }
+    let puzzle_rule = AntinodeRule::Ratio {
+        far: 2,
+        near: 1,
+        between: false,
+    };
+    rules.push((String::new(), puzzle_rule));
+    rules.push((String::new(), AntinodeRule::Harmonics));
+
+    for (label, rule) in rules {
+        if util::has_flag("--by-frequency") || util::has_flag("--draw") {
+            let by_frequency = antinodes_by_frequency(&map, rule);
+            if util::has_flag("--by-frequency") {
+                print_breakdown(&map, &by_frequency);
+            }
+            if util::has_flag("--draw") {
+                map.draw(&by_frequency.into_values().flatten().collect());
+            }
         }
-        map.push(row);
+        println!("{}{}", label, find_and_count_antinodes(&map, rule));
     }
-    println!("{}", find_and_count_antinodes(&map, false));
-    println!("{}", find_and_count_antinodes(&map, true));
+    Ok(())
 }
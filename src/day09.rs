@@ -1,5 +1,10 @@
+use anyhow::{anyhow, Result};
 use aoc_2024::util;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::fmt;
 use std::ops::Range;
+use std::str::FromStr;
 
 fn external_defrag_and_checksum(file_map: &[Range<usize>]) -> usize {
     let mut reconstructed_map = Vec::new();
@@ -36,66 +41,342 @@ fn external_defrag_and_checksum(file_map: &[Range<usize>]) -> usize {
         .sum()
 }
 
+// The longest a file can be, as its length is a single digit.
+const MAX_FILE_LEN: usize = 9;
+
+// Move each whole file, highest ID first, into the leftmost free span before it that fits, and
+// return the checksum.
+//
+// Keep a min-heap of free spans by start for each length up to MAX_FILE_LEN, with longer spans
+// (from gaps either side of an empty file running together) sharing the last heap, since any
+// file fits in them. The leftmost span that fits is then the smallest of at most nine heap tops.
+// Filling part of a span leaves a shorter span after it, and the space a file vacates never
+// matters, since the files still to move are all to its left.
 fn defrag_files_and_checksum(file_map: &[Range<usize>]) -> usize {
-    let mut reconstructed_map = Vec::new();
+    let heap_for = |len: usize| len.min(MAX_FILE_LEN);
+    let mut free_by_len: [BinaryHeap<Reverse<(usize, usize)>>; MAX_FILE_LEN + 1] =
+        Default::default();
+    let mut gaps = file_map
+        .iter()
+        .zip(file_map.iter().skip(1))
+        .map(|(prev, file)| prev.end..file.start)
+        .filter(|gap| !gap.is_empty())
+        .peekable();
+    while let Some(mut span) = gaps.next() {
+        while let Some(next) = gaps.next_if(|next| next.start == span.end) {
+            span.end = next.end;
+        }
+        free_by_len[heap_for(span.len())].push(Reverse((span.start, span.len())));
+    }
 
-    let end = file_map.last().unwrap().end;
-    reconstructed_map.resize(end + 1, None);
+    let mut checksum = 0;
+    for (id, file) in file_map.iter().enumerate().rev() {
+        let len = file.len();
+        let leftmost_fit = (heap_for(len.max(1))..free_by_len.len())
+            .filter_map(|heap| free_by_len[heap].peek().map(|&Reverse(span)| (span, heap)))
+            .min();
+        let start = match leftmost_fit {
+            Some(((start, span_len), heap)) if start < file.start => {
+                free_by_len[heap].pop();
+                if span_len > len {
+                    let rest = span_len - len;
+                    free_by_len[heap_for(rest)].push(Reverse((start + len, rest)));
+                }
+                start
+            }
+            _ => file.start,
+        };
+        checksum += id * (start..start + len).sum::<usize>();
+    }
+    checksum
+}
+
+// How to choose where files go when compacting a disk.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Strategy {
+    // Move a block at a time from the end into the leftmost free block (part 1).
+    Blocks,
+    // Move each whole file into the leftmost free span that fits (part 2).
+    FirstFit,
+    // Move each whole file into the smallest free span that fits.
+    BestFit,
+    // Move each whole file into the largest free span.
+    WorstFit,
+    // Move each whole file into the first free span that fits after where the last file went,
+    // wrapping around to the start of the disk.
+    NextFit,
+    // Split each file over at most this many free spans in a row, starting from the leftmost span
+    // that works. Split(1) is the same as FirstFit.
+    Split(usize),
+}
 
-    let mut free_list = Vec::new();
-    for (i, file) in file_map.iter().enumerate().skip(1) {
-        free_list.push(file_map[i - 1].end..file.start);
+impl FromStr for Strategy {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "blocks" => Strategy::Blocks,
+            "first-fit" => Strategy::FirstFit,
+            "best-fit" => Strategy::BestFit,
+            "worst-fit" => Strategy::WorstFit,
+            "next-fit" => Strategy::NextFit,
+            _ => {
+                let max_gaps = s
+                    .strip_prefix("split:")
+                    .and_then(|k| k.parse::<usize>().ok())
+                    .filter(|&k| k > 0)
+                    .ok_or_else(|| anyhow!("unknown strategy {}", s))?;
+                Strategy::Split(max_gaps)
+            }
+        })
     }
+}
 
-    let mut file_idx_lo = 0;
-    let mut file_idx_hi = file_map.len() - 1;
-    let mut i = 0;
+impl fmt::Display for Strategy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Strategy::Blocks => write!(f, "blocks"),
+            Strategy::FirstFit => write!(f, "first-fit"),
+            Strategy::BestFit => write!(f, "best-fit"),
+            Strategy::WorstFit => write!(f, "worst-fit"),
+            Strategy::NextFit => write!(f, "next-fit"),
+            Strategy::Split(max_gaps) => write!(f, "split:{}", max_gaps),
+        }
+    }
+}
 
-    while i < end && file_idx_lo <= file_idx_hi {
-        if file_map[file_idx_lo].contains(&i) {
-            assert!(reconstructed_map[i].is_none());
-            reconstructed_map[i] = Some(file_idx_lo);
-            i += 1;
-        } else if file_map[file_idx_lo].end == i {
-            file_idx_lo += 1;
-            // Don't increment i; not sure which branch yet.
-            continue;
-        } else {
-            let space_need = file_map[file_idx_hi].end - file_map[file_idx_hi].start;
-            let mut tmp_recon_idx = if let Some(free_range_idx) =
-                free_list.iter().position(|r| r.end - r.start >= space_need)
-            {
-                let r = free_list[free_range_idx].clone();
-                if r.start < file_map[file_idx_hi].start {
-                    free_list[free_range_idx] = (r.start + space_need)..r.end;
-                    if r.start == i {
-                        i += space_need;
-                    }
-                    r.start
-                } else {
-                    file_map[file_idx_hi].start
+// Add `span` to the sorted list of free spans, merging it with any it touches.
+fn release(free: &mut Vec<Range<usize>>, span: Range<usize>) {
+    let idx = free.partition_point(|s| s.end < span.start);
+    let mut merged = span;
+    while idx < free.len() && free[idx].start <= merged.end {
+        let s = free.remove(idx);
+        merged = merged.start.min(s.start)..merged.end.max(s.end);
+    }
+    free.insert(idx, merged);
+}
+
+// The disk block by block, for inspecting what the compaction strategies actually do.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Disk {
+    // The ID of the file in each block, or None if it's free.
+    blocks: Vec<Option<usize>>,
+    // How many blocks have been moved so far.
+    moved_blocks: usize,
+}
+
+impl Disk {
+    fn new(file_map: &[Range<usize>]) -> Self {
+        let mut blocks = vec![None; file_map.last().map_or(0, |file| file.end)];
+        for (id, file) in file_map.iter().enumerate() {
+            blocks[file.clone()].fill(Some(id));
+        }
+        Disk {
+            blocks,
+            moved_blocks: 0,
+        }
+    }
+
+    fn layout(&self) -> &[Option<usize>] {
+        &self.blocks
+    }
+
+    fn checksum(&self) -> usize {
+        self.blocks
+            .iter()
+            .enumerate()
+            .map(|(i, &file)| i * file.unwrap_or(0))
+            .sum()
+    }
+
+    // The runs of free blocks, in order.
+    fn free_spans(&self) -> Vec<Range<usize>> {
+        let mut spans: Vec<Range<usize>> = Vec::new();
+        for (i, block) in self.blocks.iter().enumerate() {
+            if block.is_some() {
+                continue;
+            }
+            match spans.last_mut() {
+                Some(span) if span.end == i => span.end += 1,
+                _ => spans.push(i..i + 1),
+            }
+        }
+        spans
+    }
+
+    // The runs of blocks holding each file, in order, indexed by ID.
+    fn file_pieces(&self) -> Vec<Vec<Range<usize>>> {
+        let max_id = self.blocks.iter().flatten().max().map_or(0, |&id| id + 1);
+        let mut files: Vec<Vec<Range<usize>>> = vec![Vec::new(); max_id];
+        for (i, block) in self.blocks.iter().enumerate() {
+            let Some(id) = *block else {
+                continue;
+            };
+            match files[id].last_mut() {
+                Some(piece) if piece.end == i => piece.end += 1,
+                _ => files[id].push(i..i + 1),
+            }
+        }
+        files
+    }
+
+    // Compact with `strategy`, calling `on_move` after each move.
+    fn compact(&mut self, strategy: Strategy, on_move: &mut dyn FnMut(&Disk)) {
+        match strategy {
+            Strategy::Blocks => self.compact_blocks(on_move),
+            _ => self.compact_files(strategy, on_move),
+        }
+    }
+
+    fn compact_blocks(&mut self, on_move: &mut dyn FnMut(&Disk)) {
+        let mut free = 0;
+        let mut used = self.blocks.len();
+        loop {
+            while free < self.blocks.len() && self.blocks[free].is_some() {
+                free += 1;
+            }
+            while used > 0 && self.blocks[used - 1].is_none() {
+                used -= 1;
+            }
+            if used == 0 || free >= used - 1 {
+                break;
+            }
+            self.blocks.swap(free, used - 1);
+            self.moved_blocks += 1;
+            on_move(self);
+        }
+    }
+
+    // Move each file once, highest ID first, into free spans before it chosen by `strategy`.
+    fn compact_files(&mut self, strategy: Strategy, on_move: &mut dyn FnMut(&Disk)) {
+        let mut free = self.free_spans();
+        // Where the last file moved to ended, for next-fit.
+        let mut cursor = 0;
+        for (id, pieces) in self.file_pieces().into_iter().enumerate().rev() {
+            let Some(first) = pieces.first() else {
+                continue;
+            };
+            let len = pieces.iter().map(|piece| piece.len()).sum::<usize>();
+            let before = free.partition_point(|span| span.end <= first.start);
+            let fits = |&idx: &usize| free[idx].len() >= len;
+            // Indices into `free` of the spans to fill, in order.
+            let chosen = match strategy {
+                Strategy::Blocks => unreachable!(),
+                Strategy::FirstFit => (0..before).find(fits).map(|idx| idx..idx + 1),
+                Strategy::BestFit => (0..before)
+                    .filter(fits)
+                    .min_by_key(|&idx| free[idx].len())
+                    .map(|idx| idx..idx + 1),
+                Strategy::WorstFit => (0..before)
+                    .filter(fits)
+                    .min_by_key(|&idx| Reverse(free[idx].len()))
+                    .map(|idx| idx..idx + 1),
+                Strategy::NextFit => {
+                    let after_cursor = free.partition_point(|span| span.start < cursor);
+                    (after_cursor..before)
+                        .chain(0..after_cursor.min(before))
+                        .find(fits)
+                        .map(|idx| idx..idx + 1)
                 }
+                Strategy::Split(max_gaps) => (0..before).find_map(|start| {
+                    let mut total = 0;
+                    (start..before.min(start + max_gaps)).find_map(|end| {
+                        total += free[end].len();
+                        (total >= len).then_some(start..end + 1)
+                    })
+                }),
+            };
+            let Some(chosen) = chosen else {
+                continue;
+            };
+
+            for piece in pieces.iter() {
+                self.blocks[piece.clone()].fill(None);
+            }
+            let mut remaining = len;
+            for idx in chosen.clone() {
+                let take = remaining.min(free[idx].len());
+                let dest = free[idx].start..free[idx].start + take;
+                self.blocks[dest.clone()].fill(Some(id));
+                free[idx].start += take;
+                remaining -= take;
+                cursor = dest.end;
+            }
+            // Every span chosen is now used up, except perhaps the last.
+            let used_up = if free[chosen.end - 1].is_empty() {
+                chosen.clone()
             } else {
-                file_map[file_idx_hi].start
+                chosen.start..chosen.end - 1
             };
-            for _ in 0..space_need {
-                assert!(reconstructed_map[tmp_recon_idx].is_none());
-                reconstructed_map[tmp_recon_idx] = Some(file_idx_hi);
-                tmp_recon_idx += 1;
+            free.drain(used_up);
+            for piece in pieces {
+                release(&mut free, piece);
             }
-            file_idx_hi -= 1;
+            self.moved_blocks += len;
+            on_move(self);
         }
     }
+}
 
-    reconstructed_map
-        .iter()
-        .enumerate()
-        .map(|(i, &file)| i * file.unwrap_or(0))
-        .sum()
+// One character per block, like the puzzle's "00...111...2...": '.' for free blocks and file IDs
+// in base 36. If IDs need more than one base-36 digit, every block is padded to the same width
+// and separated by spaces.
+impl fmt::Display for Disk {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fn base36(mut n: usize) -> String {
+            let mut digits = Vec::new();
+            loop {
+                digits.push(char::from_digit((n % 36) as u32, 36).unwrap());
+                n /= 36;
+                if n == 0 {
+                    break;
+                }
+            }
+            digits.iter().rev().collect()
+        }
+        let width = base36(self.blocks.iter().flatten().max().copied().unwrap_or(0)).len();
+        let blocks = self.blocks.iter().map(|block| match block {
+            Some(id) => format!("{:>width$}", base36(*id), width = width),
+            None => ".".repeat(width),
+        });
+        let separator = if width > 1 { " " } else { "" };
+        write!(f, "{}", blocks.collect::<Vec<_>>().join(separator))
+    }
+}
+
+// Run `strategy` on a fresh disk, printing the disk after every move if `dump` is set and the
+// final layout if `layout` is, and then a summary of the result.
+fn show_compaction(file_map: &[Range<usize>], strategy: Strategy, dump: bool, layout: bool) {
+    let mut disk = Disk::new(file_map);
+    if dump {
+        println!("{}", disk);
+    }
+    disk.compact(strategy, &mut |disk| {
+        if dump {
+            println!("{}", disk);
+        }
+    });
+    if layout && !dump {
+        println!("{}", disk);
+    }
+    // Free space after the last file isn't fragmentation.
+    let last_used = disk.layout().iter().rposition(|block| block.is_some());
+    let gaps = disk
+        .free_spans()
+        .into_iter()
+        .filter(|span| last_used.is_some_and(|last| span.start < last))
+        .collect::<Vec<_>>();
+    println!(
+        "{}: checksum {}, {} blocks moved, {} free spans of {} blocks",
+        strategy,
+        disk.checksum(),
+        disk.moved_blocks,
+        gaps.len(),
+        gaps.iter().map(|span| span.len()).sum::<usize>()
+    );
 }
 
-fn main() {
-    let input = util::get_all_input();
+// The blocks each file takes up, from a disk map alternating file and free-space lengths.
+fn parse_file_map(input: &str) -> Vec<Range<usize>> {
     let mut file_map = Vec::new();
     let mut offset = 0;
     for (i, c) in input.trim().chars().enumerate() {
@@ -105,6 +386,69 @@ fn main() {
         }
         offset = end;
     }
+    file_map
+}
+
+fn main() -> Result<()> {
+    let file_map = parse_file_map(&util::get_all_input());
+    let dump = util::has_flag("--dump");
+    let layout = util::has_flag("--layout");
+    let strategies = match util::get_flag_value("--strategies") {
+        Some(list) => list
+            .split(',')
+            .map(|s| s.parse::<Strategy>())
+            .collect::<Result<Vec<_>>>()?,
+        None if dump || layout => vec![Strategy::Blocks, Strategy::FirstFit],
+        None => Vec::new(),
+    };
+    for strategy in strategies {
+        show_compaction(&file_map, strategy, dump, layout);
+    }
     println!("{}", external_defrag_and_checksum(&file_map));
     println!("{}", defrag_files_and_checksum(&file_map));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn first_fit_checksum(file_map: &[Range<usize>]) -> usize {
+        let mut disk = Disk::new(file_map);
+        disk.compact(Strategy::FirstFit, &mut |_| {});
+        disk.checksum()
+    }
+
+    #[test]
+    fn heaps_agree_with_disk() {
+        let mut maps = vec!["2333133121414131402".to_string(), "15057".to_string()];
+        // Pseudo-random maps, with plenty of empty files and gaps.
+        let mut state = 12345_u64;
+        for _ in 0..200 {
+            let map = (0..41)
+                .map(|_| {
+                    state = state
+                        .wrapping_mul(6364136223846793005)
+                        .wrapping_add(1442695040888963407);
+                    let digit = (state >> 33) % 13;
+                    char::from_digit(digit.saturating_sub(3) as u32, 10).unwrap()
+                })
+                .collect();
+            maps.push(map);
+        }
+        for map in maps {
+            let file_map = parse_file_map(&map);
+            assert_eq!(
+                defrag_files_and_checksum(&file_map),
+                first_fit_checksum(&file_map),
+                "{}",
+                map
+            );
+        }
+        assert_eq!(
+            defrag_files_and_checksum(&parse_file_map("2333133121414131402")),
+            2858
+        );
+        assert_eq!(defrag_files_and_checksum(&parse_file_map("15057")), 56);
+    }
 }
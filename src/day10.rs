@@ -1,65 +1,540 @@
-use aoc_2024::util;
-use std::collections::{HashMap, HashSet};
-
-fn score(trails: &[Vec<u32>]) -> (usize, usize) {
-    let mut nines_reachable_from = vec![vec![HashSet::new(); trails[0].len()]; trails.len()];
-
-    let mut distinct_trails = vec![vec![0; trails[0].len()]; trails.len()];
-
-    let mut position_map = HashMap::new();
-    for (i, row) in trails.iter().enumerate() {
-        for (j, &val) in row.iter().enumerate() {
-            position_map
-                .entry(val)
-                .or_insert_with(Vec::new)
-                .push((i, j));
-        }
-    }
-    for &(i, j) in &position_map[&9] {
-        nines_reachable_from[i][j].insert((i, j));
-        distinct_trails[i][j] = 1;
-    }
-    for height in (0..9).rev() {
-        for &(i, j) in &position_map[&height] {
-            let mut reachable = HashSet::new();
-            let mut sum = 0;
-            if i > 0 && trails[i - 1][j] == height + 1 {
-                reachable.extend(&nines_reachable_from[i - 1][j]);
-                sum += distinct_trails[i - 1][j];
+use anyhow::{anyhow, bail, Result};
+use aoc_2024::util::{self, Direction};
+use std::collections::HashMap;
+use std::str::FromStr;
+
+type Pos = (usize, usize);
+
+// Heights, with None for impassable cells ('.' in the puzzle's examples).
+type Map = Vec<Vec<Option<u32>>>;
+
+// Which steps a trail may take between neighboring cells.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct StepRule {
+    // Trails climb from 0 to 9, or descend from 9 to 0.
+    uphill: bool,
+    // The most a single step may climb (or descend). Every step must change height by at least 1.
+    max_step: u32,
+}
+
+impl StepRule {
+    fn puzzle() -> Self {
+        StepRule {
+            uphill: true,
+            max_step: 1,
+        }
+    }
+
+    fn allows(self, from: u32, to: u32) -> bool {
+        let (low, high) = if self.uphill { (from, to) } else { (to, from) };
+        high > low && high - low <= self.max_step
+    }
+
+    fn trailhead_height(self) -> u32 {
+        if self.uphill {
+            0
+        } else {
+            9
+        }
+    }
+
+    fn summit_height(self) -> u32 {
+        9 - self.trailhead_height()
+    }
+}
+
+impl FromStr for StepRule {
+    type Err = anyhow::Error;
+    // "up" or "down", optionally followed by the largest step, e.g. "up:3".
+    fn from_str(s: &str) -> Result<Self> {
+        let (direction, max_step) = s.split_once(':').unwrap_or((s, "1"));
+        let uphill = match direction {
+            "up" => true,
+            "down" => false,
+            _ => bail!("unknown step direction {}", direction),
+        };
+        let max_step = max_step.parse::<u32>()?;
+        if max_step == 0 {
+            bail!("steps must be allowed to change height");
+        }
+        Ok(StepRule { uphill, max_step })
+    }
+}
+
+fn height(map: &Map, (i, j): Pos) -> Option<u32> {
+    map.get(i)?.get(j).copied().flatten()
+}
+
+fn cells_at(map: &Map, h: u32) -> impl Iterator<Item = Pos> + '_ {
+    map.iter().enumerate().flat_map(move |(i, row)| {
+        row.iter()
+            .enumerate()
+            .filter(move |&(_, &cell)| cell == Some(h))
+            .map(move |(j, _)| (i, j))
+    })
+}
+
+// The neighbors of `pos` a trail may step to next.
+fn next_steps(map: &Map, rule: StepRule, pos: Pos) -> impl Iterator<Item = Pos> + '_ {
+    let from = height(map, pos);
+    Direction::directions()
+        .into_iter()
+        .filter_map(move |d| d.neighbor(pos))
+        .filter(move |&next| {
+            from.zip(height(map, next))
+                .is_some_and(|(from, to)| rule.allows(from, to))
+        })
+}
+
+// The neighbors a trail may have come to `pos` from.
+fn prev_steps(map: &Map, rule: StepRule, pos: Pos) -> impl Iterator<Item = Pos> + '_ {
+    let to = height(map, pos);
+    Direction::directions()
+        .into_iter()
+        .filter_map(move |d| d.neighbor(pos))
+        .filter(move |&prev| {
+            height(map, prev)
+                .zip(to)
+                .is_some_and(|(from, to)| rule.allows(from, to))
+        })
+}
+
+// Every cell, ordered so that each comes after all the cells a trail can step to from it.
+fn cells_summit_first(map: &Map, rule: StepRule) -> Vec<Pos> {
+    let mut heights = (0..=9).collect::<Vec<_>>();
+    if rule.uphill {
+        heights.reverse();
+    }
+    heights.into_iter().flat_map(|h| cells_at(map, h)).collect()
+}
+
+// How to find which summits each cell can reach.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Reachability {
+    // Keep a bitset of reachable summits per cell, built up from the summits back. Fast, but
+    // takes a bit per cell per summit.
+    Bitsets,
+    // Search back from each summit in turn. Slower, but only needs memory proportional to the
+    // map.
+    PerSummitSearch,
+}
+
+impl Reachability {
+    // Bitsets unless they'd take more than this many bytes.
+    const BITSET_BUDGET: usize = 256 << 20;
+
+    fn choose(map: &Map, rule: StepRule) -> Self {
+        let cells = map.len() * map.iter().map(|row| row.len()).max().unwrap_or(0);
+        let summits = cells_at(map, rule.summit_height()).count();
+        if cells.saturating_mul(summits.div_ceil(64) * 8) <= Self::BITSET_BUDGET {
+            Reachability::Bitsets
+        } else {
+            Reachability::PerSummitSearch
+        }
+    }
+}
+
+impl FromStr for Reachability {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "bitsets" => Ok(Reachability::Bitsets),
+            "search" => Ok(Reachability::PerSummitSearch),
+            _ => bail!("unknown reachability method {}", s),
+        }
+    }
+}
+
+// Which summits can be reached from where.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Reach {
+    // The number of summits reachable from each cell.
+    scores: Vec<Vec<usize>>,
+    // The number of trailheads that can reach each summit.
+    trailheads_reaching: HashMap<Pos, usize>,
+}
+
+fn reach_with_bitsets(map: &Map, rule: StepRule, summits: &[Pos]) -> Reach {
+    let width = map.iter().map(|row| row.len()).max().unwrap_or(0);
+    let words = summits.len().div_ceil(64);
+    // Row `i * width + j` holds the bits for cell (i, j), with bit n for summits[n].
+    let mut bits = vec![0_u64; map.len() * width * words];
+    let row = |(i, j): Pos| (i * width + j) * words;
+    for (n, &summit) in summits.iter().enumerate() {
+        bits[row(summit) + n / 64] |= 1 << (n % 64);
+    }
+    let mut scores = vec![vec![0; width]; map.len()];
+    for pos in cells_summit_first(map, rule) {
+        let dst = row(pos);
+        for next in next_steps(map, rule, pos) {
+            let src = row(next);
+            // Split so the two rows can be borrowed at once; they never overlap.
+            let (dst_row, src_row) = if dst < src {
+                let (lo, hi) = bits.split_at_mut(src);
+                (&mut lo[dst..dst + words], &hi[..words])
+            } else {
+                let (lo, hi) = bits.split_at_mut(dst);
+                (&mut hi[..words], &lo[src..src + words])
+            };
+            for (d, s) in dst_row.iter_mut().zip(src_row.iter()) {
+                *d |= s;
             }
-            if j > 0 && trails[i][j - 1] == height + 1 {
-                reachable.extend(&nines_reachable_from[i][j - 1]);
-                sum += distinct_trails[i][j - 1];
+        }
+        scores[pos.0][pos.1] = bits[dst..dst + words]
+            .iter()
+            .map(|w| w.count_ones() as usize)
+            .sum();
+    }
+
+    let mut trailheads_reaching = summits.iter().map(|&s| (s, 0)).collect::<HashMap<_, _>>();
+    for trailhead in cells_at(map, rule.trailhead_height()) {
+        let start = row(trailhead);
+        for (w, &word) in bits[start..start + words].iter().enumerate() {
+            let mut word = word;
+            while word != 0 {
+                let n = w * 64 + word.trailing_zeros() as usize;
+                *trailheads_reaching.get_mut(&summits[n]).unwrap() += 1;
+                word &= word - 1;
             }
-            if i < trails.len() - 1 && trails[i + 1][j] == height + 1 {
-                reachable.extend(&nines_reachable_from[i + 1][j]);
-                sum += distinct_trails[i + 1][j];
+        }
+    }
+    Reach {
+        scores,
+        trailheads_reaching,
+    }
+}
+
+fn reach_with_search(map: &Map, rule: StepRule, summits: &[Pos]) -> Reach {
+    let width = map.iter().map(|row| row.len()).max().unwrap_or(0);
+    let mut scores = vec![vec![0; width]; map.len()];
+    let mut trailheads_reaching = HashMap::new();
+    // The last summit each cell was reached from, to avoid clearing between searches.
+    let mut seen = vec![vec![usize::MAX; width]; map.len()];
+    for (n, &summit) in summits.iter().enumerate() {
+        let mut trailheads = 0;
+        let mut stack = vec![summit];
+        seen[summit.0][summit.1] = n;
+        while let Some(pos) = stack.pop() {
+            scores[pos.0][pos.1] += 1;
+            if height(map, pos) == Some(rule.trailhead_height()) {
+                trailheads += 1;
             }
-            if j < trails[0].len() - 1 && trails[i][j + 1] == height + 1 {
-                reachable.extend(&nines_reachable_from[i][j + 1]);
-                sum += distinct_trails[i][j + 1];
+            for prev in prev_steps(map, rule, pos) {
+                if seen[prev.0][prev.1] != n {
+                    seen[prev.0][prev.1] = n;
+                    stack.push(prev);
+                }
             }
-            nines_reachable_from[i][j] = reachable;
-            distinct_trails[i][j] = sum;
         }
+        trailheads_reaching.insert(summit, trailheads);
+    }
+    Reach {
+        scores,
+        trailheads_reaching,
     }
+}
+
+fn reach(map: &Map, rule: StepRule, method: Reachability) -> Reach {
+    let summits = cells_at(map, rule.summit_height()).collect::<Vec<_>>();
+    match method {
+        Reachability::Bitsets => reach_with_bitsets(map, rule, &summits),
+        Reachability::PerSummitSearch => reach_with_search(map, rule, &summits),
+    }
+}
+
+// What's known about reaching summits from each cell.
+struct Analysis {
+    reach: Reach,
+    // The number of distinct trails from each cell to a summit.
+    ratings: Vec<Vec<usize>>,
+    // The most steps in any trail from each cell to a summit, if there is one.
+    longest: Vec<Vec<Option<usize>>>,
+}
+
+fn analyze(map: &Map, rule: StepRule, method: Reachability) -> Analysis {
+    let width = map.iter().map(|row| row.len()).max().unwrap_or(0);
+    let mut ratings = vec![vec![0; width]; map.len()];
+    let mut longest = vec![vec![None; width]; map.len()];
+    for (i, j) in cells_summit_first(map, rule) {
+        if height(map, (i, j)) == Some(rule.summit_height()) {
+            ratings[i][j] = 1;
+            longest[i][j] = Some(0);
+            continue;
+        }
+        for (ni, nj) in next_steps(map, rule, (i, j)) {
+            ratings[i][j] += ratings[ni][nj];
+            longest[i][j] = longest[i][j].max(longest[ni][nj].map(|steps: usize| steps + 1));
+        }
+    }
+    Analysis {
+        reach: reach(map, rule, method),
+        ratings,
+        longest,
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct TrailheadStats {
+    pos: Pos,
+    // The number of summits reachable.
+    score: usize,
+    // The number of distinct trails to a summit.
+    rating: usize,
+    // The most steps in any trail to a summit.
+    longest: Option<usize>,
+}
+
+fn trailhead_stats(map: &Map, rule: StepRule, analysis: &Analysis) -> Vec<TrailheadStats> {
+    cells_at(map, rule.trailhead_height())
+        .map(|(i, j)| TrailheadStats {
+            pos: (i, j),
+            score: analysis.reach.scores[i][j],
+            rating: analysis.ratings[i][j],
+            longest: analysis.longest[i][j],
+        })
+        .collect()
+}
+
+fn score(stats: &[TrailheadStats]) -> (usize, usize) {
     (
-        position_map[&0]
-            .iter()
-            .map(|&(i, j)| nines_reachable_from[i][j].len())
-            .sum(),
-        position_map[&0]
-            .iter()
-            .map(|&(i, j)| distinct_trails[i][j])
-            .sum(),
+        stats.iter().map(|s| s.score).sum(),
+        stats.iter().map(|s| s.rating).sum(),
     )
 }
 
-fn main() {
-    let mut trails = Vec::new();
-    for line in util::get_lines().map_while(Result::ok) {
-        let row = line.chars().map(|c| c.to_digit(10).unwrap()).collect();
-        trails.push(row);
+// Up to `limit` distinct trails from `start` to a summit, each as the cells along it.
+fn enumerate_trails(
+    map: &Map,
+    rule: StepRule,
+    analysis: &Analysis,
+    start: Pos,
+    limit: usize,
+) -> Vec<Vec<Pos>> {
+    fn extend(
+        map: &Map,
+        rule: StepRule,
+        analysis: &Analysis,
+        path: &mut Vec<Pos>,
+        limit: usize,
+        out: &mut Vec<Vec<Pos>>,
+    ) {
+        let pos = *path.last().unwrap();
+        if height(map, pos) == Some(rule.summit_height()) {
+            out.push(path.clone());
+            return;
+        }
+        for next in next_steps(map, rule, pos) {
+            if out.len() >= limit {
+                return;
+            }
+            // Skip dead ends.
+            if analysis.ratings[next.0][next.1] > 0 {
+                path.push(next);
+                extend(map, rule, analysis, path, limit, out);
+                path.pop();
+            }
+        }
+    }
+    let mut out = Vec::new();
+    if limit > 0 && analysis.ratings[start.0][start.1] > 0 {
+        extend(map, rule, analysis, &mut vec![start], limit, &mut out);
+    }
+    out
+}
+
+// The summit the most trails lead to, along with how many trails and how many trailheads do.
+fn most_popular_summit(
+    map: &Map,
+    rule: StepRule,
+    analysis: &Analysis,
+) -> Option<(Pos, usize, usize)> {
+    // The number of trails from any trailhead to each cell, found by working forwards from the
+    // trailheads (the reverse of `analyze`).
+    let width = map.iter().map(|row| row.len()).max().unwrap_or(0);
+    let mut trails_to = vec![vec![0; width]; map.len()];
+    for (i, j) in cells_at(map, rule.trailhead_height()) {
+        trails_to[i][j] = 1;
+    }
+    let mut heights = (0..=9).collect::<Vec<_>>();
+    if !rule.uphill {
+        heights.reverse();
+    }
+    for &h in heights.iter() {
+        for pos in cells_at(map, h) {
+            let ways = trails_to[pos.0][pos.1];
+            for (ni, nj) in next_steps(map, rule, pos) {
+                trails_to[ni][nj] += ways;
+            }
+        }
+    }
+
+    cells_at(map, rule.summit_height())
+        .map(|(i, j)| {
+            (
+                (i, j),
+                trails_to[i][j],
+                analysis.reach.trailheads_reaching[&(i, j)],
+            )
+        })
+        .max_by_key(|&(pos, trails, _)| (trails, std::cmp::Reverse(pos)))
+}
+
+fn format_trail(trail: &[Pos]) -> String {
+    trail
+        .iter()
+        .map(|(i, j)| format!("({}, {})", i, j))
+        .collect::<Vec<_>>()
+        .join(" -> ")
+}
+
+fn parse_pos(s: &str) -> Result<Pos> {
+    let (i, j) = s
+        .split_once(',')
+        .ok_or_else(|| anyhow!("expected i,j but got {}", s))?;
+    Ok((i.parse()?, j.parse()?))
+}
+
+fn parse_map(lines: impl Iterator<Item = String>) -> Map {
+    lines
+        .map(|line| {
+            line.chars()
+                .map(|c| if c == '.' { None } else { c.to_digit(10) })
+                .collect()
+        })
+        .collect()
+}
+
+fn main() -> Result<()> {
+    let map = parse_map(util::get_lines().map_while(Result::ok));
+    let rule = match util::get_flag_value("--step") {
+        Some(rule) => rule.parse::<StepRule>()?,
+        None => StepRule::puzzle(),
+    };
+
+    let method = match util::get_flag_value("--reachability") {
+        Some(method) => method.parse::<Reachability>()?,
+        None => Reachability::choose(&map, rule),
+    };
+    let analysis = analyze(&map, rule, method);
+    let all_stats = trailhead_stats(&map, rule, &analysis);
+    if util::has_flag("--trailheads") {
+        for stats in all_stats.iter() {
+            println!(
+                "trailhead {:?}: score {}, rating {}, longest trail {}",
+                stats.pos,
+                stats.score,
+                stats.rating,
+                stats
+                    .longest
+                    .map_or("none".to_string(), |steps| format!("{} steps", steps))
+            );
+        }
+    }
+    if let Some(limit) = util::get_flag_value("--trails") {
+        let limit = limit.parse::<usize>()?;
+        let starts = match util::get_flag_value("--from") {
+            Some(pos) => vec![parse_pos(&pos)?],
+            None => cells_at(&map, rule.trailhead_height()).collect(),
+        };
+        for start in starts {
+            if height(&map, start) != Some(rule.trailhead_height()) {
+                bail!("{:?} is not a trailhead", start);
+            }
+            for trail in enumerate_trails(&map, rule, &analysis, start, limit) {
+                println!("{}", format_trail(&trail));
+            }
+        }
+    }
+    if util::has_flag("--summit") {
+        if let Some((pos, trails, trailheads)) = most_popular_summit(&map, rule, &analysis) {
+            println!(
+                "most popular summit {:?}: {} trails from {} trailheads",
+                pos, trails, trailheads
+            );
+        }
+    }
+    println!("{:?}", score(&all_stats));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXAMPLE: &str = "\
+89010123
+78121874
+87430965
+96549874
+45678903
+32019012
+01329801
+10456732";
+
+    // The puzzle's smaller examples with impassable cells, and their scores or ratings.
+    const SCORE_EXAMPLES: [(&str, usize); 3] = [
+        (
+            "...0...\n...1...\n...2...\n6543456\n7.....7\n8.....8\n9.....9",
+            2,
+        ),
+        (
+            "..90..9\n...1.98\n...2..7\n6543456\n765.987\n876....\n987....",
+            4,
+        ),
+        (
+            "10..9..\n2...8..\n3...7..\n4567654\n...8..3\n...9..2\n.....01",
+            3,
+        ),
+    ];
+    const RATING_EXAMPLES: [(&str, usize); 3] = [
+        (
+            ".....0.\n..4321.\n..5..2.\n..6543.\n..7..4.\n..8765.\n..9....",
+            3,
+        ),
+        (
+            "..90..9\n...1.98\n...2..7\n6543456\n765.987\n876....\n987....",
+            13,
+        ),
+        ("012345\n123456\n234567\n345678\n4.6789\n56789.", 227),
+    ];
+
+    fn scores(input: &str, method: Reachability) -> (usize, usize) {
+        let map = parse_map(input.lines().map(String::from));
+        let rule = StepRule::puzzle();
+        score(&trailhead_stats(&map, rule, &analyze(&map, rule, method)))
+    }
+
+    #[test]
+    fn puzzle_examples() {
+        for method in [Reachability::Bitsets, Reachability::PerSummitSearch] {
+            assert_eq!(scores(EXAMPLE, method), (36, 81), "{:?}", method);
+            for (input, expected) in SCORE_EXAMPLES {
+                assert_eq!(scores(input, method).0, expected, "{:?}\n{}", method, input);
+            }
+            for (input, expected) in RATING_EXAMPLES {
+                assert_eq!(scores(input, method).1, expected, "{:?}\n{}", method, input);
+            }
+        }
+    }
+
+    #[test]
+    fn reachability_methods_agree() {
+        let inputs = [EXAMPLE]
+            .into_iter()
+            .chain(SCORE_EXAMPLES.map(|(input, _)| input))
+            .chain(RATING_EXAMPLES.map(|(input, _)| input));
+        for input in inputs {
+            let map = parse_map(input.lines().map(String::from));
+            for rule in ["up", "down", "up:2", "down:3"] {
+                let rule = rule.parse::<StepRule>().unwrap();
+                assert_eq!(
+                    reach(&map, rule, Reachability::Bitsets),
+                    reach(&map, rule, Reachability::PerSummitSearch),
+                    "{:?}\n{}",
+                    rule,
+                    input
+                );
+            }
+        }
     }
-    println!("{:?}", score(&trails));
 }
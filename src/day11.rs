@@ -1,39 +1,320 @@
+use anyhow::{anyhow, bail, Result};
 use aoc_2024::util;
-use std::collections::HashMap;
+use num_bigint::BigInt;
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
 
-fn count_rocks_after(rocks: &[u64], steps: usize) -> usize {
+// One way a stone can change when blinking.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Rule {
+    // A stone engraved with `from` becomes one engraved with `to`.
+    Replace { from: u64, to: u64 },
+    // A stone whose number of digits (in `base`) is a multiple of `parts` splits into that many
+    // stones, each taking an equal share of the digits in order.
+    Split { base: u64, parts: u32 },
+    // The stone's number is multiplied by this.
+    Multiply(u64),
+}
+
+impl Rule {
+    // The stones `n` becomes, or None if this rule doesn't apply to it. Fails if a new stone's
+    // number doesn't fit in a u64.
+    fn apply(self, n: u64) -> Result<Option<Vec<u64>>> {
+        Ok(match self {
+            Rule::Replace { from, to } => (n == from).then(|| vec![to]),
+            Rule::Split { base, parts } => {
+                let digits = n.checked_ilog(base).unwrap_or(0) + 1;
+                if !digits.is_multiple_of(parts) {
+                    return Ok(None);
+                }
+                // Only a split into one part can have a share of the digits too big for a u64,
+                // and that leaves the stone as it is.
+                let Some(divisor) = base.checked_pow(digits / parts) else {
+                    return Ok(Some(vec![n]));
+                };
+                let mut stones = Vec::new();
+                let mut rest = n;
+                for _ in 0..parts {
+                    stones.push(rest % divisor);
+                    rest /= divisor;
+                }
+                stones.reverse();
+                Some(stones)
+            }
+            Rule::Multiply(factor) => Some(vec![n
+                .checked_mul(factor)
+                .ok_or_else(|| anyhow!("stone {} * {} doesn't fit in a u64", n, factor))?]),
+        })
+    }
+}
+
+impl FromStr for Rule {
+    type Err = anyhow::Error;
+    // "A=B", "mul:K", or "split" optionally followed by ":BASE" and ":PARTS".
+    fn from_str(s: &str) -> Result<Self> {
+        if let Some((from, to)) = s.split_once('=') {
+            return Ok(Rule::Replace {
+                from: from.parse()?,
+                to: to.parse()?,
+            });
+        }
+        let mut fields = s.split(':');
+        Ok(match (fields.next(), fields.next(), fields.next()) {
+            (Some("mul"), Some(factor), None) => Rule::Multiply(factor.parse()?),
+            (Some("split"), base, parts) if fields.next().is_none() => {
+                let base = base.map_or(Ok(10), |base| base.parse())?;
+                let parts = parts.map_or(Ok(2), |parts| parts.parse())?;
+                if base < 2 || parts == 0 {
+                    bail!("bad split rule {}", s);
+                }
+                Rule::Split { base, parts }
+            }
+            _ => bail!("unknown rule {}", s),
+        })
+    }
+}
+
+// Rules tried in order, the first that applies deciding what a stone becomes. A stone no rule
+// applies to stays as it is.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct RuleSet {
+    rules: Vec<Rule>,
+}
+
+impl RuleSet {
+    fn puzzle() -> Self {
+        RuleSet {
+            rules: vec![
+                Rule::Replace { from: 0, to: 1 },
+                Rule::Split { base: 10, parts: 2 },
+                Rule::Multiply(2024),
+            ],
+        }
+    }
+
+    // The stones a stone with value `n` turns into after one blink.
+    fn apply(&self, n: u64) -> Result<Vec<u64>> {
+        for rule in self.rules.iter() {
+            if let Some(stones) = rule.apply(n)? {
+                return Ok(stones);
+            }
+        }
+        Ok(vec![n])
+    }
+}
+
+impl FromStr for RuleSet {
+    type Err = anyhow::Error;
+    // A comma-separated list of rules, e.g. "0=1,split,mul:2024" for the puzzle's.
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(RuleSet {
+            rules: s
+                .split(',')
+                .map(|rule| rule.parse())
+                .collect::<Result<_>>()?,
+        })
+    }
+}
+
+fn count_rocks_after(rocks: &[u64], steps: usize, rules: &RuleSet) -> Result<usize> {
     let mut cache = HashMap::new();
-    fn helper(cache: &mut HashMap<(u64, usize), usize>, n: u64, steps: usize) -> usize {
+    fn helper(
+        cache: &mut HashMap<(u64, usize), usize>,
+        rules: &RuleSet,
+        n: u64,
+        steps: usize,
+    ) -> Result<usize> {
         if let Some(&res) = cache.get(&(n, steps)) {
-            return res;
+            return Ok(res);
         }
         if steps == 0 {
-            return 1;
-        }
-        let res = if n == 0 {
-            helper(cache, 1, steps - 1)
-        } else {
-            let digits = n.ilog10() + 1;
-            if digits % 2 == 0 {
-                let divisor = 10_u64.pow(digits / 2);
-                helper(cache, n / divisor, steps - 1) + helper(cache, n % divisor, steps - 1)
-            } else {
-                helper(cache, n * 2024, steps - 1)
+            return Ok(1);
+        }
+        let mut res = 0;
+        for stone in rules.apply(n)? {
+            res += helper(cache, rules, stone, steps - 1)?;
+        }
+        cache.insert((n, steps), res);
+        Ok(res)
+    }
+
+    rocks
+        .iter()
+        .map(|&n| helper(&mut cache, rules, n, steps))
+        .sum()
+}
+
+// A number of stones, which may need to be arbitrarily large.
+trait Count: Clone {
+    fn checked_add(&self, rhs: &Self) -> Option<Self>;
+}
+
+impl Count for u128 {
+    fn checked_add(&self, rhs: &Self) -> Option<Self> {
+        u128::checked_add(*self, *rhs)
+    }
+}
+
+impl Count for BigInt {
+    fn checked_add(&self, rhs: &Self) -> Option<Self> {
+        Some(self + rhs)
+    }
+}
+
+// Blink once, or None if some count no longer fits in C.
+fn blink_counts<C: Count>(
+    counts: &HashMap<u64, C>,
+    rules: &RuleSet,
+) -> Result<Option<HashMap<u64, C>>> {
+    let mut next: HashMap<u64, C> = HashMap::with_capacity(counts.len());
+    for (&stone, count) in counts.iter() {
+        for new_stone in rules.apply(stone)? {
+            match next.entry(new_stone) {
+                Entry::Occupied(mut entry) => {
+                    let Some(sum) = entry.get().checked_add(count) else {
+                        return Ok(None);
+                    };
+                    entry.insert(sum);
+                }
+                Entry::Vacant(entry) => {
+                    entry.insert(count.clone());
+                }
             }
+        }
+    }
+    Ok(Some(next))
+}
+
+// How many stones there are with each value. The order of the stones never matters, so this
+// is all that's needed to keep blinking.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Stones {
+    Small(HashMap<u64, u128>),
+    // Once some count doesn't fit in a u128.
+    Big(HashMap<u64, BigInt>),
+}
+
+impl Stones {
+    fn new(rocks: &[u64]) -> Self {
+        let mut counts = HashMap::new();
+        for &rock in rocks {
+            *counts.entry(rock).or_insert(0) += 1;
+        }
+        Stones::Small(counts)
+    }
+
+    fn blink(&mut self, rules: &RuleSet) -> Result<()> {
+        *self = match self {
+            Stones::Small(counts) => match blink_counts(counts, rules)? {
+                Some(next) => Stones::Small(next),
+                None => {
+                    let big = counts
+                        .iter()
+                        .map(|(&stone, &count)| (stone, BigInt::from(count)))
+                        .collect();
+                    // BigInt counts never overflow.
+                    Stones::Big(blink_counts(&big, rules)?.unwrap())
+                }
+            },
+            Stones::Big(counts) => Stones::Big(blink_counts(counts, rules)?.unwrap()),
         };
-        cache.insert((n, steps), res);
-        res
+        Ok(())
+    }
+
+    fn distinct(&self) -> usize {
+        match self {
+            Stones::Small(counts) => counts.len(),
+            Stones::Big(counts) => counts.len(),
+        }
     }
 
-    rocks.iter().map(|&n| helper(&mut cache, n, steps)).sum()
+    // Each stone value with its count, in order of value.
+    fn distribution(&self) -> Vec<(u64, BigInt)> {
+        let mut distribution = match self {
+            Stones::Small(counts) => counts
+                .iter()
+                .map(|(&stone, &count)| (stone, BigInt::from(count)))
+                .collect::<Vec<_>>(),
+            Stones::Big(counts) => counts
+                .iter()
+                .map(|(&stone, count)| (stone, count.clone()))
+                .collect(),
+        };
+        distribution.sort();
+        distribution
+    }
+
+    fn total(&self) -> BigInt {
+        match self {
+            Stones::Small(counts) => counts.values().map(|&count| BigInt::from(count)).sum(),
+            Stones::Big(counts) => counts.values().sum(),
+        }
+    }
 }
 
-fn main() {
+// The first blink after which the set of distinct stone values never changes (looking at most
+// `max_blinks` ahead), along with how many values that set has.
+//
+// The set after a blink depends only on the set before it, so it's stable as soon as one blink
+// leaves it unchanged.
+fn stabilization_point(
+    rocks: &[u64],
+    rules: &RuleSet,
+    max_blinks: usize,
+) -> Result<Option<(usize, usize)>> {
+    let mut values = rocks.iter().copied().collect::<HashSet<_>>();
+    for blink in 0..max_blinks {
+        let mut next = HashSet::new();
+        for &stone in values.iter() {
+            next.extend(rules.apply(stone)?);
+        }
+        if next == values {
+            return Ok(Some((blink, values.len())));
+        }
+        values = next;
+    }
+    Ok(None)
+}
+
+fn main() -> Result<()> {
     let input = util::get_all_input();
     let rocks = input
         .split_ascii_whitespace()
         .map(|s| s.parse::<u64>().unwrap())
         .collect::<Vec<_>>();
-    println!("{}", count_rocks_after(&rocks, 25));
-    println!("{}", count_rocks_after(&rocks, 75));
+    let rules = match util::get_flag_value("--rules") {
+        Some(rules) => rules.parse::<RuleSet>()?,
+        None => RuleSet::puzzle(),
+    };
+    if let Some(blinks) = util::get_flag_value("--blinks") {
+        let blinks = blinks.parse::<usize>().unwrap();
+        let mut stones = Stones::new(&rocks);
+        for _ in 0..blinks {
+            stones.blink(&rules)?;
+        }
+        if util::has_flag("--distribution") {
+            for (stone, count) in stones.distribution() {
+                println!("{}: {}", stone, count);
+            }
+        }
+        println!(
+            "after {} blinks: {} stones, {} distinct values",
+            blinks,
+            stones.total(),
+            stones.distinct()
+        );
+    }
+    if util::has_flag("--stabilize") {
+        match stabilization_point(&rocks, &rules, 1000)? {
+            Some((blink, distinct)) => println!(
+                "distinct values stabilize after {} blinks at {} values",
+                blink, distinct
+            ),
+            None => println!("distinct values don't stabilize within 1000 blinks"),
+        }
+    }
+    println!("{}", count_rocks_after(&rocks, 25, &rules)?);
+    println!("{}", count_rocks_after(&rocks, 75, &rules)?);
+    Ok(())
 }
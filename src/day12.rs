@@ -1,167 +1,245 @@
 use aoc_2024::util;
-use std::collections::{HashMap, HashSet, VecDeque};
 
-fn sum_costs(garden: &[Vec<char>]) -> (u64, u64) {
-    let mut visited = vec![vec![false; garden[0].len()]; garden.len()];
-    let mut cost = 0;
-    let mut bulk_cost = 0;
+type Pos = (usize, usize);
 
-    for (i, row) in garden.iter().enumerate() {
-        for (j, &start) in row.iter().enumerate() {
-            if visited[i][j] {
-                continue;
-            }
-            // bfs from here for extent
-            let mut queue = VecDeque::new();
-            queue.push_back((i, j));
-            let mut perim = 0;
-            let mut area = 0;
-
-            let mut union_find = HashMap::new();
-
-            while let Some((next_i, next_j)) = queue.pop_front() {
-                if visited[next_i][next_j] {
-                    continue;
-                }
-                visited[next_i][next_j] = true;
-
-                area += 1;
-
-                if next_i > 0 && garden[next_i - 1][next_j] == start {
-                    queue.push_back((next_i - 1, next_j));
-                } else {
-                    perim += 1;
-
-                    let key = (next_i, next_j, Direction::Up);
-                    add_and_maybe_union_neighbors(
-                        &mut union_find,
-                        key,
-                        next_j.checked_sub(1).map(|j| (next_i, j)),
-                        (next_i, next_j + 1),
-                    );
-                }
-
-                if next_i < garden.len() - 1 && garden[next_i + 1][next_j] == start {
-                    queue.push_back((next_i + 1, next_j));
-                } else {
-                    perim += 1;
-
-                    let key = (next_i, next_j, Direction::Down);
-                    add_and_maybe_union_neighbors(
-                        &mut union_find,
-                        key,
-                        next_j.checked_sub(1).map(|j| (next_i, j)),
-                        (next_i, next_j + 1),
-                    );
-                }
-
-                if next_j > 0 && garden[next_i][next_j - 1] == start {
-                    queue.push_back((next_i, next_j - 1));
-                } else {
-                    perim += 1;
-
-                    let key = (next_i, next_j, Direction::Left);
-                    add_and_maybe_union_neighbors(
-                        &mut union_find,
-                        key,
-                        next_i.checked_sub(1).map(|i| (i, next_j)),
-                        (next_i + 1, next_j),
-                    );
-                }
-
-                if next_j < garden[0].len() - 1 && garden[next_i][next_j + 1] == start {
-                    queue.push_back((next_i, next_j + 1));
-                } else {
-                    perim += 1;
-
-                    let key = (next_i, next_j, Direction::Right);
-                    add_and_maybe_union_neighbors(
-                        &mut union_find,
-                        key,
-                        next_i.checked_sub(1).map(|i| (i, next_j)),
-                        (next_i + 1, next_j),
-                    );
-                }
-            }
-
-            cost += area * perim;
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Region {
+    plant: char,
+    cells: Vec<Pos>,
+    area: u64,
+    perimeter: u64,
+    sides: u64,
+}
 
-            let mut roots = HashSet::new();
-            let keys = union_find.keys().cloned().collect::<Vec<_>>();
-            for &key in keys.iter() {
-                roots.insert(find(&mut union_find, key));
-            }
+impl Region {
+    // The top-left and bottom-right cells of the smallest rectangle holding the region.
+    fn bounding_box(&self) -> (Pos, Pos) {
+        let top = self.cells.iter().map(|&(i, _)| i).min().unwrap();
+        let left = self.cells.iter().map(|&(_, j)| j).min().unwrap();
+        let bottom = self.cells.iter().map(|&(i, _)| i).max().unwrap();
+        let right = self.cells.iter().map(|&(_, j)| j).max().unwrap();
+        ((top, left), (bottom, right))
+    }
+}
 
-            bulk_cost += area * (roots.len() as u64);
+// Find the regions, labeling each cell with its region's index into the result.
+//
+// After labeling, area, perimeter and sides all come from one pass over the cells. A region
+// has as many sides as corners, and each corner belongs to one cell: for each diagonal
+// direction, a cell has an outside corner there if neither neighbor towards it is in its
+// region, and an inside corner if both are but the diagonal neighbor isn't.
+fn find_regions(garden: &[Vec<char>]) -> (Vec<Region>, Vec<Vec<usize>>) {
+    let (labels, count) = util::label_components(garden, |a, b| a == b);
+    let mut regions = vec![
+        Region {
+            plant: ' ',
+            cells: Vec::new(),
+            area: 0,
+            perimeter: 0,
+            sides: 0,
+        };
+        count
+    ];
+    for (i, row) in labels.iter().enumerate() {
+        for (j, &label) in row.iter().enumerate() {
+            let same = |di: isize, dj: isize| {
+                i.checked_add_signed(di)
+                    .zip(j.checked_add_signed(dj))
+                    .and_then(|(i, j)| labels.get(i)?.get(j))
+                    .is_some_and(|&other| other == label)
+            };
+            let region = &mut regions[label];
+            region.plant = garden[i][j];
+            region.cells.push((i, j));
+            region.area += 1;
+            region.perimeter += [(-1, 0), (1, 0), (0, -1), (0, 1)]
+                .iter()
+                .filter(|&&(di, dj)| !same(di, dj))
+                .count() as u64;
+            region.sides += [(-1, -1), (-1, 1), (1, -1), (1, 1)]
+                .iter()
+                .filter(|&&(di, dj)| {
+                    let (vertical, horizontal) = (same(di, 0), same(0, dj));
+                    (!vertical && !horizontal) || (vertical && horizontal && !same(di, dj))
+                })
+                .count() as u64;
         }
     }
-    (cost, bulk_cost)
+    (regions, labels)
 }
 
-#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
-enum Direction {
-    Up,
-    Down,
-    Left,
-    Right,
+fn sum_costs(regions: &[Region]) -> (u64, u64) {
+    (
+        regions.iter().map(|r| r.area * r.perimeter).sum(),
+        regions.iter().map(|r| r.area * r.sides).sum(),
+    )
 }
 
-type HashKey = (usize, usize, Direction);
+// The regions entirely surrounded by region `id`, i.e. those it cuts off from the outside of the
+// garden.
+//
+// Flood fills everything but region `id` from just outside its bounding box; any cell that
+// isn't reached is enclosed.
+fn enclosed_by(labels: &[Vec<usize>], regions: &[Region], id: usize) -> Vec<usize> {
+    let ((top, left), (bottom, right)) = regions[id].bounding_box();
+    // The bounding box with a margin of one cell, which may be outside the garden.
+    let (height, width) = (bottom - top + 3, right - left + 3);
+    let label_at = |bi: usize, bj: usize| -> Option<usize> {
+        let i = (bi + top).checked_sub(1)?;
+        let j = (bj + left).checked_sub(1)?;
+        labels.get(i)?.get(j).copied()
+    };
 
-fn find(union_find: &mut HashMap<HashKey, (HashKey, u64)>, mut a: HashKey) -> HashKey {
-    let mut root = a;
-    while union_find[&root].0 != root {
-        root = union_find[&root].0;
+    // The margin is a ring of cells outside the region, so starting anywhere on it reaches all
+    // of it.
+    let mut reached = vec![vec![false; width]; height];
+    reached[0][0] = true;
+    let mut stack: Vec<Pos> = vec![(0, 0)];
+    while let Some((bi, bj)) = stack.pop() {
+        let neighbors = [
+            (bi.wrapping_sub(1), bj),
+            (bi + 1, bj),
+            (bi, bj.wrapping_sub(1)),
+            (bi, bj + 1),
+        ];
+        for (ni, nj) in neighbors {
+            if ni < height && nj < width && !reached[ni][nj] && label_at(ni, nj) != Some(id) {
+                reached[ni][nj] = true;
+                stack.push((ni, nj));
+            }
+        }
     }
 
-    while union_find[&a].0 != root {
-        let parent = union_find[&a].0;
-        union_find.insert(a, (root, union_find[&a].1));
-        a = parent;
+    let mut enclosed = Vec::new();
+    for (bi, row) in reached.iter().enumerate() {
+        for (bj, &reached) in row.iter().enumerate() {
+            match label_at(bi, bj) {
+                Some(label) if !reached && label != id => enclosed.push(label),
+                _ => {}
+            }
+        }
     }
-    root
+    enclosed.sort();
+    enclosed.dedup();
+    enclosed
 }
 
-fn union(union_find: &mut HashMap<HashKey, (HashKey, u64)>, a: HashKey, b: HashKey) {
-    let a = find(union_find, a);
-    let b = find(union_find, b);
-    if a == b {
-        return;
-    }
+fn enclosed(labels: &[Vec<usize>], regions: &[Region]) -> Vec<Vec<usize>> {
+    (0..regions.len())
+        .map(|id| enclosed_by(labels, regions, id))
+        .collect()
+}
 
-    let a_size = union_find[&a].1;
-    let b_size = union_find[&b].1;
-    if a_size < b_size {
-        union_find.insert(b, (a, b_size));
-        union_find.insert(a, (union_find[&a].0, a_size + b_size));
-    } else {
-        union_find.insert(a, (b, a_size));
-        union_find.insert(b, (union_find[&b].0, a_size + b_size));
+fn print_report_table(regions: &[Region], enclosed: &[Vec<usize>]) {
+    println!(
+        "{:>5} {:>5} {:>6} {:>9} {:>6}  {:<20} encloses",
+        "id", "plant", "area", "perimeter", "sides", "bounding box"
+    );
+    for (id, region) in regions.iter().enumerate() {
+        let (top_left, bottom_right) = region.bounding_box();
+        let encloses = if enclosed[id].is_empty() {
+            "-".to_string()
+        } else {
+            enclosed[id]
+                .iter()
+                .map(|id| id.to_string())
+                .collect::<Vec<_>>()
+                .join(",")
+        };
+        println!(
+            "{:>5} {:>5} {:>6} {:>9} {:>6}  {:<20} {}",
+            id,
+            region.plant,
+            region.area,
+            region.perimeter,
+            region.sides,
+            format!("{:?}-{:?}", top_left, bottom_right),
+            encloses
+        );
     }
 }
 
-fn add_and_maybe_union_neighbors(
-    union_find: &mut HashMap<HashKey, (HashKey, u64)>,
-    key: HashKey,
-    small_neighbor: Option<(usize, usize)>,
-    large_neighbor: (usize, usize),
-) {
-    union_find.insert(key, (key, 1));
-
-    let direction = key.2;
+fn print_report_json(regions: &[Region], enclosed: &[Vec<usize>]) {
+    println!("[");
+    for (id, region) in regions.iter().enumerate() {
+        let ((top, left), (bottom, right)) = region.bounding_box();
+        println!(
+            "  {{\"id\": {}, \"plant\": {:?}, \"area\": {}, \"perimeter\": {}, \"sides\": {}, \
+             \"bounding_box\": {{\"top\": {}, \"left\": {}, \"bottom\": {}, \"right\": {}}}, \
+             \"encloses\": {:?}}}{}",
+            id,
+            region.plant.to_string(),
+            region.area,
+            region.perimeter,
+            region.sides,
+            top,
+            left,
+            bottom,
+            right,
+            enclosed[id],
+            if id + 1 < regions.len() { "," } else { "" }
+        );
+    }
+    println!("]");
+}
 
-    if let Some((i, j)) = small_neighbor {
-        if union_find.contains_key(&(i, j, direction)) {
-            union(union_find, key, (i, j, direction));
+// The box-drawing character joining fences going up, down, left and right from a point.
+fn box_corner(up: bool, down: bool, left: bool, right: bool) -> char {
+    match (up, down, left, right) {
+        (false, false, false, false) => ' ',
+        (true, true, false, false) | (true, false, false, false) | (false, true, false, false) => {
+            '│'
+        }
+        (false, false, true, true) | (false, false, true, false) | (false, false, false, true) => {
+            '─'
         }
+        (false, true, false, true) => '┌',
+        (false, true, true, false) => '┐',
+        (true, false, false, true) => '└',
+        (true, false, true, false) => '┘',
+        (true, true, false, true) => '├',
+        (true, true, true, false) => '┤',
+        (false, true, true, true) => '┬',
+        (true, false, true, true) => '┴',
+        (true, true, true, true) => '┼',
     }
+}
 
-    if union_find.contains_key(&(large_neighbor.0, large_neighbor.1, direction)) {
-        union(
-            union_find,
-            key,
-            (large_neighbor.0, large_neighbor.1, direction),
-        );
+// Draw the fences around region `id` with box-drawing characters, with each of its cells shown
+// as its plant.
+fn draw_region(labels: &[Vec<usize>], regions: &[Region], id: usize) {
+    let ((top, left), (bottom, right)) = regions[id].bounding_box();
+    let (rows, cols) = ((bottom - top + 1) as isize, (right - left + 1) as isize);
+    // Whether the cell at (r, c), relative to the bounding box, is in the region.
+    let inside = |r: isize, c: isize| -> bool {
+        (0..rows).contains(&r)
+            && (0..cols).contains(&c)
+            && labels[top + r as usize][left + c as usize] == id
+    };
+    // Fences between horizontally and vertically adjacent cells.
+    let vertical_fence = |r: isize, c: isize| inside(r, c - 1) != inside(r, c);
+    let horizontal_fence = |r: isize, c: isize| inside(r - 1, c) != inside(r, c);
+
+    // Odd positions are cells and even ones the lines between them.
+    for y in 0..=2 * rows {
+        let mut line = String::new();
+        for x in 0..=2 * cols {
+            let (r, c) = (y / 2, x / 2);
+            line.push(match (y % 2 == 1, x % 2 == 1) {
+                (true, true) if inside(r, c) => regions[id].plant,
+                (true, true) => ' ',
+                (true, false) if vertical_fence(r, c) => '│',
+                (false, true) if horizontal_fence(r, c) => '─',
+                (true, false) | (false, true) => ' ',
+                (false, false) => box_corner(
+                    vertical_fence(r - 1, c),
+                    vertical_fence(r, c),
+                    horizontal_fence(r, c - 1),
+                    horizontal_fence(r, c),
+                ),
+            });
+        }
+        println!("{}", line.trim_end());
     }
 }
 
@@ -170,5 +248,56 @@ fn main() {
     for line in util::get_lines().map_while(Result::ok) {
         garden.push(line.chars().collect());
     }
-    println!("{:?}", sum_costs(&garden));
+    let (regions, labels) = find_regions(&garden);
+    if util::has_flag("--report") {
+        // "--report json" for JSON, or a table otherwise.
+        if util::get_flag_value("--report").as_deref() == Some("json") {
+            print_report_json(&regions, &enclosed(&labels, &regions));
+        } else {
+            print_report_table(&regions, &enclosed(&labels, &regions));
+        }
+    }
+    if let Some(pos) = util::get_flag_value("--region") {
+        let (i, j) = pos
+            .split_once(',')
+            .map(|(i, j)| (i.parse::<usize>().unwrap(), j.parse::<usize>().unwrap()))
+            .expect("--region takes i,j");
+        let id = *labels
+            .get(i)
+            .and_then(|row| row.get(j))
+            .unwrap_or_else(|| panic!("{},{} is outside the garden", i, j));
+        draw_region(&labels, &regions, id);
+    }
+    println!("{:?}", sum_costs(&regions));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn costs(input: &str) -> (u64, u64) {
+        let garden = input
+            .lines()
+            .map(|line| line.chars().collect())
+            .collect::<Vec<Vec<char>>>();
+        sum_costs(&find_regions(&garden).0)
+    }
+
+    #[test]
+    fn puzzle_examples() {
+        assert_eq!(costs("AAAA\nBBCD\nBBCC\nEEEC"), (140, 80));
+        assert_eq!(costs("OOOOO\nOXOXO\nOOOOO\nOXOXO\nOOOOO"), (772, 436));
+        assert_eq!(
+            costs(
+                "RRRRIICCFF\nRRRRIICCCF\nVVRRRCCFFF\nVVRCCCJFFF\nVVVVCJJCFE\n\
+                 VVIVCCJJEE\nVVIIICJJEE\nMIIIIIJJEE\nMIIISIJEEE\nMMMISSJEEE"
+            ),
+            (1930, 1206)
+        );
+        assert_eq!(costs("EEEEE\nEXXXX\nEEEEE\nEXXXX\nEEEEE").1, 236);
+        assert_eq!(
+            costs("AAAAAA\nAAABBA\nAAABBA\nABBAAA\nABBAAA\nAAAAAA").1,
+            368
+        );
+    }
 }
@@ -45,7 +45,7 @@ struct EquationSolution {
 fn solve_equation(a: u64, b: u64, target: u64) -> Option<EquationSolution> {
     let (gcd, a_out, b_out) = extended_gcd(a.try_into().unwrap(), b.try_into().unwrap());
 
-    if target % gcd != 0 {
+    if !target.is_multiple_of(gcd) {
         return None;
     }
     let b_coeff: i64 = (b / gcd).try_into().unwrap();
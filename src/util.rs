@@ -3,11 +3,14 @@ use std::fs::{read_to_string, File};
 use std::io::{BufRead, BufReader};
 use std::path::Path;
 
-pub fn get_lines() -> std::io::Lines<BufReader<File>> {
+pub fn open_input() -> File {
     let arg = env::args().nth(1).expect("Need file to read");
     let path = Path::new(&arg);
-    let file = File::open(path).unwrap_or_else(|_| panic!("could not open {}", path.display()));
-    BufReader::new(file).lines()
+    File::open(path).unwrap_or_else(|_| panic!("could not open {}", path.display()))
+}
+
+pub fn get_lines() -> std::io::Lines<BufReader<File>> {
+    BufReader::new(open_input()).lines()
 }
 
 pub fn get_all_input() -> String {
@@ -15,6 +18,18 @@ pub fn get_all_input() -> String {
     read_to_string(&arg).unwrap_or_else(|_| panic!("Could not read {}", arg))
 }
 
+// Returns whether `flag` (e.g. "--explain") was passed after the input file.
+pub fn has_flag(flag: &str) -> bool {
+    env::args().skip(2).any(|arg| arg == flag)
+}
+
+// Returns the argument following `flag`, if `flag` was passed after the input file.
+pub fn get_flag_value(flag: &str) -> Option<String> {
+    let mut args = env::args().skip(2);
+    args.find(|arg| arg == flag)?;
+    args.next()
+}
+
 #[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
 pub enum Direction {
     Up,
@@ -58,3 +73,65 @@ impl Direction {
         ]
     }
 }
+
+// Label the connected components of a grid, where cells next to each other (not diagonally) are
+// connected if `connected` says so. Returns each cell's label and the number of components;
+// components are numbered in the order their first cells appear, row by row.
+//
+// Scans the grid once, giving each cell the label of the cell above or to its left where
+// connected, and merging the two labels with a union-find when both are. A final pass replaces
+// each label by its component's number.
+pub fn label_components<T>(
+    grid: &[Vec<T>],
+    connected: impl Fn(&T, &T) -> bool,
+) -> (Vec<Vec<usize>>, usize) {
+    fn find(parent: &mut [usize], mut label: usize) -> usize {
+        while parent[label] != label {
+            parent[label] = parent[parent[label]];
+            label = parent[label];
+        }
+        label
+    }
+
+    let mut parent = Vec::new();
+    let mut labels = grid
+        .iter()
+        .map(|row| vec![0; row.len()])
+        .collect::<Vec<_>>();
+    for (i, row) in grid.iter().enumerate() {
+        for (j, cell) in row.iter().enumerate() {
+            let up = i
+                .checked_sub(1)
+                .and_then(|up| grid[up].get(j))
+                .filter(|above| connected(above, cell))
+                .map(|_| labels[i - 1][j]);
+            let left = j
+                .checked_sub(1)
+                .filter(|&left| connected(&row[left], cell))
+                .map(|left| labels[i][left]);
+            labels[i][j] = match (up, left) {
+                (Some(up), Some(left)) => {
+                    let (up, left) = (find(&mut parent, up), find(&mut parent, left));
+                    parent[left] = up;
+                    up
+                }
+                (Some(label), None) | (None, Some(label)) => label,
+                (None, None) => {
+                    parent.push(parent.len());
+                    parent.len() - 1
+                }
+            };
+        }
+    }
+
+    let mut numbers = vec![None; parent.len()];
+    let mut count = 0;
+    for label in labels.iter_mut().flatten() {
+        let root = find(&mut parent, *label);
+        *label = *numbers[root].get_or_insert_with(|| {
+            count += 1;
+            count - 1
+        });
+    }
+    (labels, count)
+}